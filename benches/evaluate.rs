@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+
+use bumpalo::Bump;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use jsonata_rs::{ArrayFlags, JsonAta, Value};
+
+const EXPR: &str = "$sum(items[group = 'a'].id)";
+const ITEMS: u32 = 1_000;
+
+/// Everything allocated during evaluation stays in the arena until it's dropped, so runs are
+/// split into chunks of this many, each with a fresh arena, to keep memory use bounded.
+const CHUNK: u64 = 100;
+
+/// Builds a document with `len` items, e.g. `{"items": [{"id": 0, "group": "a"}, ...]}`.
+fn document(arena: &Bump, len: u32) -> &Value<'_> {
+    let items = Value::array_with_capacity(arena, len as usize, ArrayFlags::empty());
+    for i in 0..len {
+        items.push(Value::object_of(
+            arena,
+            [
+                ("id", &*Value::number(arena, i)),
+                (
+                    "group",
+                    Value::string(arena, ["a", "b", "c"][i as usize % 3]),
+                ),
+            ],
+        ));
+    }
+    Value::object_of(arena, [("items", &*items)])
+}
+
+/// Splits `iters` runs into chunks of at most [`CHUNK`].
+fn chunks(iters: u64) -> impl Iterator<Item = u64> {
+    (0..iters)
+        .step_by(CHUNK as usize)
+        .map(move |i| CHUNK.min(iters - i))
+}
+
+/// Evaluating a pre-built input in place, compared with evaluating the same document as a JSON
+/// string, which has to be parsed into a new `Value` on each call.
+fn evaluate_input(c: &mut Criterion) {
+    let input_json = document(&Bump::new(), ITEMS).serialize(false);
+
+    let mut group = c.benchmark_group("evaluate_input");
+    group.bench_function("evaluate_value", |b| {
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            for chunk in chunks(iters) {
+                let arena = Bump::new();
+                let input = document(&arena, ITEMS);
+                let jsonata = JsonAta::new(EXPR, &arena).unwrap();
+                let start = Instant::now();
+                for _ in 0..chunk {
+                    black_box(jsonata.evaluate_value(black_box(input)).unwrap());
+                }
+                elapsed += start.elapsed();
+            }
+            elapsed
+        })
+    });
+    group.bench_function("evaluate_json", |b| {
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            for chunk in chunks(iters) {
+                let arena = Bump::new();
+                let jsonata = JsonAta::new(EXPR, &arena).unwrap();
+                let start = Instant::now();
+                for _ in 0..chunk {
+                    black_box(
+                        jsonata
+                            .evaluate(Some(black_box(&input_json)), None)
+                            .unwrap(),
+                    );
+                }
+                elapsed += start.elapsed();
+            }
+            elapsed
+        })
+    });
+    group.finish();
+}
+
+/// Cloning copies only the top level of a value, so wrapping a small or a large document in a
+/// single-member array should make no difference to what it costs to clone.
+fn clone_value(c: &mut Criterion) {
+    let mut group = c.benchmark_group("clone_value");
+    for (name, len) in [("small", 1), ("large", ITEMS)] {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for chunk in chunks(iters) {
+                    let arena = Bump::new();
+                    let value: &Value = Value::array_of(&arena, [document(&arena, len)]);
+                    let start = Instant::now();
+                    for _ in 0..chunk {
+                        black_box(black_box(value).clone(&arena));
+                    }
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, evaluate_input, clone_value);
+criterion_main!(benches);
@@ -44,7 +44,7 @@ fn main() {
     match jsonata {
         Ok(jsonata) => {
             if opt.ast {
-                println!("{:#?}", jsonata.ast());
+                print!("{}", jsonata.ast());
                 return;
             }
 
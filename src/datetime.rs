@@ -1,4 +1,6 @@
-use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono::{
+    DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc,
+};
 use num_format::{Locale, ToFormattedString};
 
 use crate::Error;
@@ -124,9 +126,7 @@ fn handle_pattern(pattern: &str, date: &DateTime<FixedOffset>) -> Result<String,
         "D" => Ok(date.format("%-d").to_string()),
         "M" => Ok(date.format("%-m").to_string()),
         "C" => Ok("ISO".to_string()),
-        s => Err(Error::D3137Error(format!(
-            "Unsupported datetime picture string: {s}"
-        ))),
+        s => Err(Error::D3132UnknownComponent(s.to_string())),
     }
 }
 
@@ -288,7 +288,7 @@ pub fn parse_custom_format(timestamp_str: &str, picture: &str) -> Option<i64> {
             let year_str = parts[2..].join(" ");
             let year = match year_str.parse::<i32>() {
                 Ok(num) => num,
-                Err(_) => words_to_number(&year_str)? as i32, // If it's word-based (e.g., 'two thousand and seventeen')
+                Err(_) => words_to_number(&year_str)?, // If it's word-based (e.g., 'two thousand and seventeen')
             };
             println!("year {}", year);
 
@@ -521,7 +521,7 @@ pub fn parse_custom_format(timestamp_str: &str, picture: &str) -> Option<i64> {
             let month = month_name_to_int(parts[1])?;
 
             let year_str = parts[2..].join(" ");
-            let year = words_to_number(&year_str.to_lowercase())? as i32;
+            let year = words_to_number(&year_str.to_lowercase())?;
 
             let parsed_date = NaiveDate::from_ymd_opt(year, month, day)?;
             let time = NaiveTime::from_hms_opt(0, 0, 0)?;
@@ -543,7 +543,7 @@ pub fn parse_custom_format(timestamp_str: &str, picture: &str) -> Option<i64> {
             let month = month_name_to_int(parts[1])?;
 
             let year_str = parts[2..].join(" ");
-            let year = words_to_number(&year_str.to_lowercase())? as i32;
+            let year = words_to_number(&year_str.to_lowercase())?;
 
             let parsed_date = NaiveDate::from_ymd_opt(year, month, day)?;
             let time = NaiveTime::from_hms_opt(0, 0, 0)?;
@@ -566,7 +566,7 @@ pub fn parse_custom_format(timestamp_str: &str, picture: &str) -> Option<i64> {
             let month = month_name_to_int(parts[1])?; // Handle the month (e.g., "August")
 
             let year_str = parts[2..].join(" ");
-            let year = words_to_number(&year_str.to_lowercase())? as i32;
+            let year = words_to_number(&year_str.to_lowercase())?;
 
             let parsed_date = NaiveDate::from_ymd_opt(year, month, day)?;
             let time = NaiveTime::from_hms_opt(0, 0, 0)?;
@@ -580,6 +580,214 @@ pub fn parse_custom_format(timestamp_str: &str, picture: &str) -> Option<i64> {
     }
 }
 
+/// The components of a date/time picture that [`parse_picture`] can read, from most to least
+/// significant. A timezone (`Z` or `z`) can also be read, but isn't part of the date or time.
+const NUMERIC_COMPONENTS: [char; 7] = ['Y', 'M', 'D', 'H', 'm', 's', 'f'];
+
+/// All component specifiers, including those that can only be formatted.
+const KNOWN_COMPONENTS: &str = "YMDdFWwXxHhPmsfZzCE";
+
+enum PictureSegment {
+    Literal(String),
+    Component { specifier: char, width: usize },
+}
+
+/// Splits a picture into literal text and `[...]` components, where `[[` and `]]` are literal
+/// brackets. A component's width is the number of digits in its presentation, ignoring any
+/// width modifier after a comma.
+fn split_picture(picture: &str) -> Result<Vec<PictureSegment>, Error> {
+    let mut segments = vec![];
+    let mut literal = String::new();
+    let mut chars = picture.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '[' if chars.peek() == Some(&'[') => {
+                chars.next();
+                literal.push('[');
+            }
+            ']' if chars.peek() == Some(&']') => {
+                chars.next();
+                literal.push(']');
+            }
+            '[' => {
+                let mut component = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c) if !c.is_whitespace() => component.push(c),
+                        Some(_) => (),
+                        None => {
+                            return Err(Error::D3135PictureStringNoClosingBracketError(
+                                picture.to_string(),
+                            ))
+                        }
+                    }
+                }
+
+                if !literal.is_empty() {
+                    segments.push(PictureSegment::Literal(std::mem::take(&mut literal)));
+                }
+
+                let mut component_chars = component.chars();
+                let specifier = component_chars.next().unwrap_or(' ');
+                let presentation: String = component_chars.take_while(|c| *c != ',').collect();
+                segments.push(PictureSegment::Component {
+                    specifier,
+                    width: presentation
+                        .chars()
+                        .filter(|c| c.is_ascii_digit() || *c == '#')
+                        .count(),
+                });
+
+                if !KNOWN_COMPONENTS.contains(specifier) {
+                    return Err(Error::D3132UnknownComponent(component));
+                }
+                if specifier == 'Z' || specifier == 'z' {
+                    continue;
+                }
+                if !NUMERIC_COMPONENTS.contains(&specifier)
+                    || presentation
+                        .chars()
+                        .any(|c| !c.is_ascii_digit() && c != '#')
+                {
+                    // A component that can be formatted but not parsed, like a month name
+                    segments.pop();
+                    segments.push(PictureSegment::Component {
+                        specifier: ' ',
+                        width: 0,
+                    });
+                }
+            }
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        segments.push(PictureSegment::Literal(literal));
+    }
+
+    Ok(segments)
+}
+
+/// Parses a timestamp with a picture made of numeric year, month, day, hour, minute, second and
+/// fractional second components, e.g. `[Y0001]-[M01]-[D01]T[H01]:[m01]:[s01]`. The time is UTC
+/// unless the picture has a timezone component, which reads offsets like `+05:00`, `-0500`, `Z`
+/// or `GMT+05:00`.
+///
+/// Components more significant than those in the picture default to the current date, and less
+/// significant ones to their minimum, but there can't be gaps in between. Returns `None` if the
+/// timestamp doesn't match the picture, or if the picture uses a component that can only be
+/// formatted (like a month name).
+pub fn parse_picture(timestamp: &str, picture: &str) -> Result<Option<i64>, Error> {
+    let segments = split_picture(picture)?;
+
+    let mut values: [Option<i64>; 7] = [None; 7];
+    let mut offset_seconds = 0;
+    let mut rest = timestamp;
+
+    for (index, segment) in segments.iter().enumerate() {
+        match segment {
+            PictureSegment::Literal(literal) => match rest.strip_prefix(literal.as_str()) {
+                Some(remaining) => rest = remaining,
+                None => return Ok(None),
+            },
+            PictureSegment::Component { specifier: ' ', .. } => return Ok(None),
+            PictureSegment::Component {
+                specifier: 'Z' | 'z',
+                ..
+            } => {
+                rest = rest.strip_prefix("GMT").unwrap_or(rest);
+                if let Some(remaining) = rest.strip_prefix('Z') {
+                    rest = remaining;
+                    continue;
+                }
+
+                let len = rest
+                    .char_indices()
+                    .take_while(|(i, c)| {
+                        (*i == 0 && (*c == '+' || *c == '-')) || c.is_ascii_digit() || *c == ':'
+                    })
+                    .count();
+                let (zone, remaining) = rest.split_at(len);
+                match parse_timezone_offset(&zone.replace(':', "")) {
+                    Some(offset) => offset_seconds = offset.local_minus_utc() as i64,
+                    None => return Ok(None),
+                }
+                rest = remaining;
+            }
+            PictureSegment::Component { specifier, width } => {
+                // Without a literal to separate it from the next component, a component has to
+                // be exactly as wide as its presentation
+                let adjacent = matches!(
+                    segments.get(index + 1),
+                    Some(PictureSegment::Component { .. })
+                );
+                let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+                let digits = if adjacent { digits.min(*width) } else { digits };
+                if digits == 0 {
+                    return Ok(None);
+                }
+
+                let (number, remaining) = rest.split_at(digits);
+                rest = remaining;
+
+                // A fraction of a second, so only the first three digits are milliseconds
+                let value = if *specifier == 'f' {
+                    format!("{:0<3}", number)[..3].parse()
+                } else {
+                    number.parse()
+                };
+                let position = NUMERIC_COMPONENTS
+                    .iter()
+                    .position(|c| c == specifier)
+                    .unwrap();
+                match value {
+                    Ok(value) => values[position] = Some(value),
+                    Err(_) => return Ok(None),
+                }
+            }
+        }
+    }
+
+    if !rest.is_empty() {
+        return Ok(None);
+    }
+
+    // A picture without any components is just literal text, which doesn't identify a time
+    let present: Vec<usize> = (0..values.len()).filter(|i| values[*i].is_some()).collect();
+    let (Some(&first), Some(&last)) = (present.first(), present.last()) else {
+        return Ok(None);
+    };
+    if present.len() != last - first + 1 {
+        return Err(Error::D3136MissingComponents(picture.to_string()));
+    }
+
+    let now = Utc::now();
+    let component = |i: usize, current: u32, minimum: u32| {
+        values[i].unwrap_or(if i < first { current } else { minimum } as i64)
+    };
+
+    let datetime = (|| {
+        let date = NaiveDate::from_ymd_opt(
+            i32::try_from(values[0].unwrap_or(now.year() as i64)).ok()?,
+            u32::try_from(component(1, now.month(), 1)).ok()?,
+            u32::try_from(component(2, now.day(), 1)).ok()?,
+        )?;
+        let time = NaiveTime::from_hms_milli_opt(
+            u32::try_from(component(3, now.hour(), 0)).ok()?,
+            u32::try_from(component(4, now.minute(), 0)).ok()?,
+            u32::try_from(component(5, now.second(), 0)).ok()?,
+            u32::try_from(component(6, 0, 0)).ok()?,
+        )?;
+        Some(NaiveDateTime::new(date, time))
+    })();
+
+    Ok(datetime.map(|datetime| {
+        Utc.from_utc_datetime(&datetime).timestamp_millis() - offset_seconds * 1000
+    }))
+}
+
 fn parse_day_str(day_str: &str) -> String {
     // Split the day string on hyphen, convert to lowercase, and join it back
     day_str
@@ -848,7 +1056,10 @@ pub fn parse_timezone_offset(timezone: &str) -> Option<FixedOffset> {
     if timezone == "0000" {
         return FixedOffset::east_opt(0); // UTC
     }
-    if timezone.len() != 5 {
+    if timezone.len() != 5
+        || !timezone.is_ascii()
+        || !timezone[1..].bytes().all(|b| b.is_ascii_digit())
+    {
         return None;
     }
 
@@ -856,6 +1067,9 @@ pub fn parse_timezone_offset(timezone: &str) -> Option<FixedOffset> {
         timezone[1..3].parse::<i32>().ok()?,
         timezone[3..5].parse::<i32>().ok()?,
     );
+    if minutes >= 60 {
+        return None;
+    }
     let total_offset_seconds = (hours * 3600) + (minutes * 60);
 
     match &timezone[0..1] {
@@ -931,7 +1145,7 @@ fn to_words(num: u32) -> String {
         below_20[num as usize].to_string()
     } else if num < 100 {
         // Handle multiples of 10 (20, 30, etc.)
-        if num % 10 == 0 {
+        if num.is_multiple_of(10) {
             return tens[(num / 10) as usize].to_string();
         }
         // Handle numbers between 21-99
@@ -1386,19 +1600,10 @@ fn parse_iso8601_date(date_str: &str) -> Option<i64> {
 }
 
 fn parse_iso8601_with_timezone(date_str: &str) -> Option<i64> {
-    // Normalize the timezone format from "+0000" to "+00:00"
-    let normalized_str = if date_str.ends_with("+0000") {
-        date_str.replace("+0000", "+00:00")
-    } else {
-        date_str.to_string()
-    };
-
-    if let Ok(datetime) = DateTime::parse_from_rfc3339(&normalized_str) {
-        // Convert the parsed datetime to milliseconds since the Unix Epoch
-        Some(datetime.timestamp_millis())
-    } else {
-        None
-    }
+    // The offset can be written without a colon, e.g. "+0500" rather than "+05:00"
+    DateTime::parse_from_str(date_str, "%Y-%m-%dT%H:%M:%S%.f%z")
+        .ok()
+        .map(|datetime| datetime.timestamp_millis())
 }
 
 fn parse_date_only(date_str: &str) -> Option<i64> {
@@ -22,28 +22,57 @@ pub enum Error {
     S0214ExpectedVarRight(usize, String),
     S0215BindingAfterPredicates(usize),
     S0216BindingAfterSort(usize),
+    S0301EmptyRegex(usize),
+    S0302UnterminatedRegex(usize),
+    S0303InvalidRegex(usize, String),
+    S0401TypeParametersNotAllowed(usize),
+    S0402ChoiceGroupTypeParameters(usize),
 
     // Runtime errors
-    D1001NumberOfOutRange(f64),
+    D1001NumberOfOutRange(usize, f64),
     D1002NegatingNonNumeric(usize, String),
+    D1004ZeroLengthMatch(usize),
     D1009MultipleKeys(usize, String),
     D2014RangeOutOfBounds(usize, isize),
     D3001StringNotFinite(usize),
     D3010EmptyPattern(usize),
     D3011NegativeLimit(usize),
+    D3012InvalidReplacementType(usize, String),
     D3020NegativeLimit(usize),
     D3030NonNumericCast(usize, String),
+    D3040NegativeLimit(usize),
     D3050SecondArguement(String),
     D3060SqrtNegative(usize, String),
     D3061PowUnrepresentable(usize, String, String),
     D3070InvalidDefaultSort(usize),
-    D3141Assert(String),
+    D3080TooManySubPictures(usize),
+    D3081MultipleDecimalSeparators(usize),
+    D3082MultiplePercents(usize),
+    D3083MultiplePerMilles(usize),
+    D3084PercentAndPerMille(usize),
+    D3085NoMantissaDigits(usize),
+    D3086PassiveBetweenActive(usize),
+    D3087GroupingAdjacentToDecimal(usize),
+    D3088GroupingAtEndOfInteger(usize),
+    D3089AdjacentGroupingSeparators(usize),
+    D3090DigitBeforeOptionalDigit(usize),
+    D3091OptionalDigitBeforeDigit(usize),
+    D3092ExponentWithPercent(usize),
+    D3093InvalidExponent(usize),
+    D3120EvalSyntaxError(usize, String),
+    D3121EvalDynamicError(usize, String),
+    D3141Assert(usize, String),
     D3137Error(String),
+    /// Raised by `$error(message, code, data)`, with the position of the call, the message, and
+    /// the code and data object passed to it, if any. Its code is the one passed, or else D3137.
+    D3137UserError(usize, String, Option<String>, Option<serde_json::Value>),
     D3138Error(String),
     D3139Error(String),
+    D3132UnknownComponent(String),
     D3133PictureStringNameModifierError(String),
     D3134TooManyTzDigits(String),
     D3135PictureStringNoClosingBracketError(String),
+    D3136MissingComponents(String),
 
     // Type errors
     T0410ArgumentNotValid(usize, usize, String),
@@ -51,6 +80,8 @@ pub enum Error {
     T1003NonStringKey(usize, String),
     T1005InvokedNonFunctionSuggest(usize, String),
     T1006InvokedNonFunction(usize),
+    T1007PartialApplyNonFunctionSuggest(usize, String),
+    T1008PartialApplyNonFunction(usize),
     T2001LeftSideNotNumber(usize, String),
     T2002RightSideNotNumber(usize, String),
     T2003LeftSideNotInteger(usize),
@@ -67,10 +98,31 @@ pub enum Error {
     // Expression timebox/depth errors
     U1001StackOverflow,
     U1001Timeout,
+
+    // Errors specific to this implementation, which JSONata doesn't raise
+    X0001SortComparatorNotBoolean(usize),
+    X0002ParentUnsupported(usize),
 }
 
 impl error::Error for Error {}
 
+/// The broad category of an [`Error`], as given by the first letter of its code. Errors with an
+/// `Xxxxx` code, which is specific to this implementation, are given the category they'd have in
+/// JSONata, and errors raised by `$error` with a custom code are [`ErrorKind::User`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Sxxxx - static errors, raised when parsing an expression
+    Static,
+    /// Txxxx - type errors
+    Type,
+    /// Dxxxx - dynamic errors, raised during evaluation
+    Dynamic,
+    /// Uxxxx - evaluation exceeded its depth or time limits
+    Limit,
+    /// A custom code given to `$error`, which can be any non-empty string
+    User,
+}
+
 impl Error {
     /**
      * Error codes
@@ -85,6 +137,9 @@ impl Error {
      *  10xx    - evaluator
      *  20xx    - operators
      *  3xxx    - functions (blocks of 10 for each function)
+     *
+     * Uxxxx    - Depth and time limits
+     * Xxxxx    - Errors specific to this implementation, numbered in the order they were added
      */
     pub fn code(&self) -> &str {
         match *self {
@@ -108,26 +163,54 @@ impl Error {
             Error::S0214ExpectedVarRight(..) => "S0214",
             Error::S0215BindingAfterPredicates(..) => "S0215",
             Error::S0216BindingAfterSort(..) => "S0216",
+            Error::S0301EmptyRegex(..) => "S0301",
+            Error::S0302UnterminatedRegex(..) => "S0302",
+            Error::S0303InvalidRegex(..) => "S0303",
+            Error::S0401TypeParametersNotAllowed(..) => "S0401",
+            Error::S0402ChoiceGroupTypeParameters(..) => "S0402",
 
             // Runtime errors
             Error::D1001NumberOfOutRange(..) => "D1001",
             Error::D1002NegatingNonNumeric(..) => "D1002",
+            Error::D1004ZeroLengthMatch(..) => "D1004",
             Error::D1009MultipleKeys(..) => "D1009",
             Error::D2014RangeOutOfBounds(..) => "D2014",
             Error::D3001StringNotFinite(..) => "D3001",
             Error::D3010EmptyPattern(..) => "D3010",
             Error::D3011NegativeLimit(..) => "D3011",
+            Error::D3012InvalidReplacementType(..) => "D3012",
             Error::D3020NegativeLimit(..) => "D3020",
             Error::D3030NonNumericCast(..) => "D3030",
+            Error::D3040NegativeLimit(..) => "D3040",
             Error::D3050SecondArguement(..) => "D3050",
             Error::D3060SqrtNegative(..) => "D3060",
             Error::D3061PowUnrepresentable(..) => "D3061",
             Error::D3070InvalidDefaultSort(..) => "D3070",
+            Error::D3080TooManySubPictures(..) => "D3080",
+            Error::D3081MultipleDecimalSeparators(..) => "D3081",
+            Error::D3082MultiplePercents(..) => "D3082",
+            Error::D3083MultiplePerMilles(..) => "D3083",
+            Error::D3084PercentAndPerMille(..) => "D3084",
+            Error::D3085NoMantissaDigits(..) => "D3085",
+            Error::D3086PassiveBetweenActive(..) => "D3086",
+            Error::D3087GroupingAdjacentToDecimal(..) => "D3087",
+            Error::D3088GroupingAtEndOfInteger(..) => "D3088",
+            Error::D3089AdjacentGroupingSeparators(..) => "D3089",
+            Error::D3090DigitBeforeOptionalDigit(..) => "D3090",
+            Error::D3091OptionalDigitBeforeDigit(..) => "D3091",
+            Error::D3092ExponentWithPercent(..) => "D3092",
+            Error::D3093InvalidExponent(..) => "D3093",
+            Error::D3120EvalSyntaxError(..) => "D3120",
+            Error::D3121EvalDynamicError(..) => "D3121",
+            Error::D3132UnknownComponent(..) => "D3132",
             Error::D3133PictureStringNameModifierError(..) => "D3133",
             Error::D3134TooManyTzDigits(..) => "D3134",
             Error::D3135PictureStringNoClosingBracketError(..) => "D3135",
+            Error::D3136MissingComponents(..) => "D3136",
             Error::D3141Assert(..) => "D3141",
             Error::D3137Error(..) => "D3137",
+            Error::D3137UserError(_, _, Some(ref code), _) => code,
+            Error::D3137UserError(..) => "D3137",
             Error::D3138Error(..) => "D3138",
             Error::D3139Error(..) => "D3139",
 
@@ -137,6 +220,8 @@ impl Error {
             Error::T1003NonStringKey(..) => "T1003",
             Error::T1005InvokedNonFunctionSuggest(..) => "T1005",
             Error::T1006InvokedNonFunction(..) => "T1006",
+            Error::T1007PartialApplyNonFunctionSuggest(..) => "T1007",
+            Error::T1008PartialApplyNonFunction(..) => "T1008",
             Error::T2001LeftSideNotNumber(..) => "T2001",
             Error::T2002RightSideNotNumber(..) => "T2002",
             Error::T2003LeftSideNotInteger(..) => "T2003",
@@ -153,10 +238,187 @@ impl Error {
             // Expression timebox/depth errors
             Error::U1001StackOverflow => "U1001",
             Error::U1001Timeout => "U1001",
+
+            // Errors specific to this implementation
+            Error::X0001SortComparatorNotBoolean(..) => "X0001",
+            Error::X0002ParentUnsupported(..) => "X0002",
         }
     }
 }
 
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::D3137UserError(_, _, Some(_), _) => return ErrorKind::User,
+            Error::X0001SortComparatorNotBoolean(..) => return ErrorKind::Dynamic,
+            Error::X0002ParentUnsupported(..) => return ErrorKind::Static,
+            _ => (),
+        }
+        // Anything not classified by its code is raised while evaluating
+        match self.code().as_bytes().first() {
+            Some(b'S') => ErrorKind::Static,
+            Some(b'T') => ErrorKind::Type,
+            Some(b'U') => ErrorKind::Limit,
+            _ => ErrorKind::Dynamic,
+        }
+    }
+
+    /// The character index in the expression source that the error relates to, if it has one.
+    pub fn position(&self) -> Option<usize> {
+        match *self {
+            Error::S0101UnterminatedStringLiteral(p, ..)
+            | Error::S0102LexedNumberOutOfRange(p, ..)
+            | Error::S0103UnsupportedEscape(p, ..)
+            | Error::S0104InvalidUnicodeEscape(p, ..)
+            | Error::S0105UnterminatedQuoteProp(p, ..)
+            | Error::S0106UnterminatedComment(p, ..)
+            | Error::S0201SyntaxError(p, ..)
+            | Error::S0202UnexpectedToken(p, ..)
+            | Error::S0204UnknownOperator(p, ..)
+            | Error::S0203ExpectedTokenBeforeEnd(p, ..)
+            | Error::S0208InvalidFunctionParam(p, ..)
+            | Error::S0209InvalidPredicate(p, ..)
+            | Error::S0210MultipleGroupBy(p, ..)
+            | Error::S0211InvalidUnary(p, ..)
+            | Error::S0212ExpectedVarLeft(p, ..)
+            | Error::S0213InvalidStep(p, ..)
+            | Error::S0214ExpectedVarRight(p, ..)
+            | Error::S0215BindingAfterPredicates(p, ..)
+            | Error::S0216BindingAfterSort(p, ..)
+            | Error::S0301EmptyRegex(p, ..)
+            | Error::S0302UnterminatedRegex(p, ..)
+            | Error::S0303InvalidRegex(p, ..)
+            | Error::S0401TypeParametersNotAllowed(p, ..)
+            | Error::S0402ChoiceGroupTypeParameters(p, ..)
+            | Error::D1002NegatingNonNumeric(p, ..)
+            | Error::D1004ZeroLengthMatch(p, ..)
+            | Error::D1009MultipleKeys(p, ..)
+            | Error::D2014RangeOutOfBounds(p, ..)
+            | Error::D1001NumberOfOutRange(p, ..)
+            | Error::D3001StringNotFinite(p, ..)
+            | Error::D3010EmptyPattern(p, ..)
+            | Error::D3011NegativeLimit(p, ..)
+            | Error::D3012InvalidReplacementType(p, ..)
+            | Error::D3020NegativeLimit(p, ..)
+            | Error::D3030NonNumericCast(p, ..)
+            | Error::D3040NegativeLimit(p, ..)
+            | Error::D3060SqrtNegative(p, ..)
+            | Error::D3061PowUnrepresentable(p, ..)
+            | Error::D3070InvalidDefaultSort(p, ..)
+            | Error::D3080TooManySubPictures(p, ..)
+            | Error::D3081MultipleDecimalSeparators(p, ..)
+            | Error::D3082MultiplePercents(p, ..)
+            | Error::D3083MultiplePerMilles(p, ..)
+            | Error::D3084PercentAndPerMille(p, ..)
+            | Error::D3085NoMantissaDigits(p, ..)
+            | Error::D3086PassiveBetweenActive(p, ..)
+            | Error::D3087GroupingAdjacentToDecimal(p, ..)
+            | Error::D3088GroupingAtEndOfInteger(p, ..)
+            | Error::D3089AdjacentGroupingSeparators(p, ..)
+            | Error::D3090DigitBeforeOptionalDigit(p, ..)
+            | Error::D3091OptionalDigitBeforeDigit(p, ..)
+            | Error::D3092ExponentWithPercent(p, ..)
+            | Error::D3093InvalidExponent(p, ..)
+            | Error::D3120EvalSyntaxError(p, ..)
+            | Error::D3121EvalDynamicError(p, ..)
+            | Error::D3141Assert(p, ..)
+            | Error::D3137UserError(p, ..)
+            | Error::T0410ArgumentNotValid(p, ..)
+            | Error::T0412ArgumentMustBeArrayOfType(p, ..)
+            | Error::T1003NonStringKey(p, ..)
+            | Error::T1005InvokedNonFunctionSuggest(p, ..)
+            | Error::T1006InvokedNonFunction(p, ..)
+            | Error::T1007PartialApplyNonFunctionSuggest(p, ..)
+            | Error::T1008PartialApplyNonFunction(p, ..)
+            | Error::T2001LeftSideNotNumber(p, ..)
+            | Error::T2002RightSideNotNumber(p, ..)
+            | Error::T2003LeftSideNotInteger(p, ..)
+            | Error::T2004RightSideNotInteger(p, ..)
+            | Error::T2006RightSideNotFunction(p, ..)
+            | Error::T2007CompareTypeMismatch(p, ..)
+            | Error::T2008InvalidOrderBy(p, ..)
+            | Error::T2009BinaryOpMismatch(p, ..)
+            | Error::T2010BinaryOpTypes(p, ..)
+            | Error::T2011UpdateNotObject(p, ..)
+            | Error::T2012DeleteNotStrings(p, ..)
+            | Error::T2013BadClone(p, ..)
+            | Error::X0001SortComparatorNotBoolean(p, ..)
+            | Error::X0002ParentUnsupported(p, ..) => Some(p),
+
+            Error::D3050SecondArguement(..)
+            | Error::D3137Error(..)
+            | Error::D3138Error(..)
+            | Error::D3139Error(..)
+            | Error::D3132UnknownComponent(..)
+            | Error::D3133PictureStringNameModifierError(..)
+            | Error::D3134TooManyTzDigits(..)
+            | Error::D3135PictureStringNoClosingBracketError(..)
+            | Error::D3136MissingComponents(..)
+            | Error::U1001StackOverflow
+            | Error::U1001Timeout => None,
+        }
+    }
+
+    /// The 1-based line and column in `source` that the error relates to, if it has a position.
+    pub fn line_and_column(&self, source: &str) -> Option<(usize, usize)> {
+        self.position().map(|p| line_and_column(source, p))
+    }
+}
+
+/// Constructors for the errors raised most often, so that call sites don't depend on the fields
+/// of each variant.
+impl Error {
+    /// D1002: the operand of unary `-` is not a number.
+    pub fn d1002(position: usize, value: impl ToString) -> Self {
+        Error::D1002NegatingNonNumeric(position, value.to_string())
+    }
+
+    /// T0410: an argument doesn't match the signature of the function it's passed to.
+    pub fn t0410(position: usize, argument: usize, function: impl ToString) -> Self {
+        Error::T0410ArgumentNotValid(position, argument, function.to_string())
+    }
+
+    /// T2001: the left side of an arithmetic operator is not a number.
+    pub fn t2001(position: usize, op: impl ToString) -> Self {
+        Error::T2001LeftSideNotNumber(position, op.to_string())
+    }
+
+    /// T2002: the right side of an arithmetic operator is not a number.
+    pub fn t2002(position: usize, op: impl ToString) -> Self {
+        Error::T2002RightSideNotNumber(position, op.to_string())
+    }
+
+    /// T2009: the sides of a comparison are a number and a string.
+    pub fn t2009(
+        position: usize,
+        lhs: impl ToString,
+        rhs: impl ToString,
+        op: impl ToString,
+    ) -> Self {
+        Error::T2009BinaryOpMismatch(position, lhs.to_string(), rhs.to_string(), op.to_string())
+    }
+
+    /// T2010: a side of a comparison is neither a number nor a string.
+    pub fn t2010(position: usize, op: impl ToString) -> Self {
+        Error::T2010BinaryOpTypes(position, op.to_string())
+    }
+}
+
+/// Converts a character index in `source` into a 1-based line and column.
+pub fn line_and_column(source: &str, char_index: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut column = 1;
+    for c in source.chars().take(char_index) {
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
 impl fmt::Display for Error {
     #[allow(clippy::many_single_char_names)]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -204,10 +466,22 @@ impl fmt::Display for Error {
                 write!(f, "{}: A context variable binding must precede any predicates on a step", p),
             S0216BindingAfterSort(ref p) =>
                 write!(f, "{}: A context variable binding must precede the 'order-by' clause on a step", p),
+            S0301EmptyRegex(ref p) =>
+                write!(f, "{}: Empty regular expressions are not allowed", p),
+            S0302UnterminatedRegex(ref p) =>
+                write!(f, "{}: No terminating / in regular expression", p),
+            S0303InvalidRegex(ref p, ref m) =>
+                write!(f, "{}: Invalid regular expression: {}", p, m),
+            S0401TypeParametersNotAllowed(ref p) =>
+                write!(f, "{}: Type parameters can only be applied to functions and arrays", p),
+            S0402ChoiceGroupTypeParameters(ref p) =>
+                write!(f, "{}: Choice groups containing parameterized types are not supported", p),
             // Runtime errors
-            D1001NumberOfOutRange(ref n) => write!(f, "Number out of range: {}", n),
+            D1001NumberOfOutRange(ref p, ref n) => write!(f, "{}: Number out of range: {}", p, n),
             D1002NegatingNonNumeric(ref p, ref v) =>
                 write!(f, "{}: Cannot negate a non-numeric value `{}`", p, v),
+            D1004ZeroLengthMatch(ref p) =>
+                write!(f, "{}: Regular expression matches zero length string", p),
             D1009MultipleKeys(ref p, ref k) =>
                 write!(f, "{}: Multiple key definitions evaluate to same key: {}", p, k),
             D2014RangeOutOfBounds(ref p, ref s) =>
@@ -218,10 +492,14 @@ impl fmt::Display for Error {
                 write!(f, "{}: Second argument of replace function cannot be an empty string", p),
             D3011NegativeLimit(ref p) =>
                 write!(f, "{}: Fourth argument of replace function must evaluate to a positive number", p),
+            D3012InvalidReplacementType(ref p, ref v) =>
+                write!(f, "{}: Attempted to replace a matched string with a non-string value: {}", p, v),
             D3020NegativeLimit(ref p) =>
                 write!(f, "{}: Third argument of split function must evaluate to a positive number", p),
             D3030NonNumericCast(ref p, ref n) =>
                 write!(f, "{}: Unable to cast value to a number: {}", p, n),
+            D3040NegativeLimit(ref p) =>
+                write!(f, "{}: Third argument of match function must evaluate to a positive number", p),
             D3050SecondArguement(ref p) =>
                 write!(f, "{}: The second argument of reduce function must be a function with at least two arguments", p),
             D3060SqrtNegative(ref p, ref n) =>
@@ -230,16 +508,54 @@ impl fmt::Display for Error {
                 write!(f, "{}: The power function has resulted in a value that cannot be represented as a JSON number: base={}, exponent={}", p, b, e),
             D3070InvalidDefaultSort(ref p) =>
                 write!(f, "{}: The single argument form of the sort function can only be applied to an array of strings or an array of numbers.  Use the second argument to specify a comparison function", p),
+            D3080TooManySubPictures(ref p) =>
+                write!(f, "{}: The picture string must only contain a maximum of two sub-pictures", p),
+            D3081MultipleDecimalSeparators(ref p) =>
+                write!(f, "{}: The sub-picture must not contain more than one instance of the 'decimal-separator' character", p),
+            D3082MultiplePercents(ref p) =>
+                write!(f, "{}: The sub-picture must not contain more than one instance of the 'percent' character", p),
+            D3083MultiplePerMilles(ref p) =>
+                write!(f, "{}: The sub-picture must not contain more than one instance of the 'per-mille' character", p),
+            D3084PercentAndPerMille(ref p) =>
+                write!(f, "{}: The sub-picture must not contain both a 'percent' and a 'per-mille' character", p),
+            D3085NoMantissaDigits(ref p) =>
+                write!(f, "{}: The mantissa part of a sub-picture must contain at least one character that is either an 'optional digit character' or a member of the 'decimal digit family'", p),
+            D3086PassiveBetweenActive(ref p) =>
+                write!(f, "{}: The sub-picture must not contain a passive character that is preceded by an active character and that is followed by another active character", p),
+            D3087GroupingAdjacentToDecimal(ref p) =>
+                write!(f, "{}: The sub-picture must not contain a 'grouping-separator' character that appears adjacent to a 'decimal-separator' character", p),
+            D3088GroupingAtEndOfInteger(ref p) =>
+                write!(f, "{}: The sub-picture must not contain a 'grouping-separator' at the end of the integer part", p),
+            D3089AdjacentGroupingSeparators(ref p) =>
+                write!(f, "{}: The sub-picture must not contain two adjacent instances of the 'grouping-separator' character", p),
+            D3090DigitBeforeOptionalDigit(ref p) =>
+                write!(f, "{}: The integer part of the sub-picture must not contain a member of the 'decimal digit family' that is followed by an instance of the 'optional digit character'", p),
+            D3091OptionalDigitBeforeDigit(ref p) =>
+                write!(f, "{}: The fractional part of the sub-picture must not contain an instance of the 'optional digit character' that is followed by a member of the 'decimal digit family'", p),
+            D3092ExponentWithPercent(ref p) =>
+                write!(f, "{}: A sub-picture that contains a 'percent' or 'per-mille' character must not contain a character treated as an 'exponent-separator'", p),
+            D3093InvalidExponent(ref p) =>
+                write!(f, "{}: The exponent part of the sub-picture must comprise only of one or more characters that are members of the 'decimal digit family'", p),
+            D3120EvalSyntaxError(ref p, ref m) =>
+                write!(f, "{}: Syntax error in expression passed to function eval: {}", p, m),
+            D3121EvalDynamicError(ref p, ref m) =>
+                write!(f, "{}: Dynamic error evaluating the expression passed to function eval: {}", p, m),
+            D3132UnknownComponent(ref c) =>
+                write!(f, "Unknown component specifier {} in date/time picture string", c),
             D3133PictureStringNameModifierError(ref m) =>
                 write!(f, "{}: The 'name' modifier can only be applied to months and days in the date/time picture string, not Y", m),
             D3134TooManyTzDigits(ref m) =>
                 write!(f, "{}: The timezone integer format specifier cannot have more than four digits", m),
             D3135PictureStringNoClosingBracketError(ref m) =>
                 write!(f, "{}: No matching closing bracket ']' in date/time picture string", m),
-            D3141Assert(ref m) =>
-                write!(f, "{}", m),
+            D3136MissingComponents(ref p) =>
+                write!(f, "The date/time picture string {} is missing specifiers required to parse the timestamp", p),
+            D3141Assert(ref p, ref m) =>
+                write!(f, "{}: {}", p, m),
             D3137Error(ref m) =>
                 write!(f, "{}", m),
+            D3137UserError(ref p, ref m, ..) =>
+                write!(f, "{}: {}", p, m),
             D3138Error(ref m) =>
                 write!(f, "{}: The $single() function expected exactly 1 matching result.  Instead it matched more.", m),
             D3139Error(ref m) =>
@@ -255,6 +571,10 @@ impl fmt::Display for Error {
                 write!(f, "{}: Attempted to invoke a non-function. Did you mean ${}?", p, t),
             T1006InvokedNonFunction(ref p) =>
                 write!(f, "{}: Attempted to invoke a non-function", p),
+            T1007PartialApplyNonFunctionSuggest(ref p, ref t) =>
+                write!(f, "{}: Attempted to partially apply a non-function. Did you mean ${}?", p, t),
+            T1008PartialApplyNonFunction(ref p) =>
+                write!(f, "{}: Attempted to partially apply a non-function", p),
             T2001LeftSideNotNumber(ref p, ref o) =>
                 write!( f, "{}: The left side of the `{}` operator must evaluate to a number", p, o),
             T2002RightSideNotNumber(ref p, ref o) =>
@@ -283,7 +603,12 @@ impl fmt::Display for Error {
             U1001StackOverflow =>
                 write!(f, "Stack overflow error: Check for non-terminating recursive function.  Consider rewriting as tail-recursive."),
             U1001Timeout =>
-                write!(f, "Expression evaluation timeout: Check for infinite loop")
+                write!(f, "Expression evaluation timeout: Check for infinite loop"),
+            // Errors specific to this implementation
+            X0001SortComparatorNotBoolean(ref p) =>
+                write!(f, "{}: The comparison function passed to the sort function must return a boolean", p),
+            X0002ParentUnsupported(ref p) =>
+                write!(f, "{}: The parent operator `%` is not supported by this implementation", p),
         }
     }
 }
@@ -293,15 +618,10 @@ impl fmt::Display for Error {
 // "S0207": "Unexpected end of expression",
 // "S0217": "The object representing the 'parent' cannot be derived from this expression",
 
-// "S0301": "Empty regular expressions are not allowed",
-// "S0302": "No terminating / in regular expression",
 // "S0402": "Choice groups containing parameterized types are not supported",
 // "S0401": "Type parameters can only be applied to functions and arrays",
 // "S0500": "Attempted to evaluate an expression containing syntax error(s)",
 // "T0411": "Context value is not a compatible type with argument {{index}} of function {{token}}",
-// "D1004": "Regular expression matches zero length string",
-// "T1007": "Attempted to partially apply a non-function. Did you mean ${{{token}}}?",
-// "T1008": "Attempted to partially apply a non-function",
 // // "T1010": "The matcher function argument passed to function {{token}} does not return the correct object structure",
 // "D2005": "The left side of := must be a variable name (start with $)",  // defunct - replaced by S0212 parser error
 // define_error!(
@@ -311,35 +631,87 @@ impl fmt::Display for Error {
 // );
 // "D3010": "Second argument of replace function cannot be an empty string",
 // "D3011": "Fourth argument of replace function must evaluate to a positive number",
-// "D3012": "Attempted to replace a matched string with a non-string value",
 // "D3020": "Third argument of split function must evaluate to a positive number",
 // "D3040": "Third argument of match function must evaluate to a positive number",
 // "D3050": "The second argument of reduce function must be a function with at least two arguments",
-// "D3080": "The picture string must only contain a maximum of two sub-pictures",
-// "D3081": "The sub-picture must not contain more than one instance of the 'decimal-separator' character",
-// "D3082": "The sub-picture must not contain more than one instance of the 'percent' character",
-// "D3083": "The sub-picture must not contain more than one instance of the 'per-mille' character",
-// "D3084": "The sub-picture must not contain both a 'percent' and a 'per-mille' character",
-// "D3085": "The mantissa part of a sub-picture must contain at least one character that is either an 'optional digit character' or a member of the 'decimal digit family'",
-// "D3086": "The sub-picture must not contain a passive character that is preceded by an active character and that is followed by another active character",
-// "D3087": "The sub-picture must not contain a 'grouping-separator' character that appears adjacent to a 'decimal-separator' character",
-// "D3088": "The sub-picture must not contain a 'grouping-separator' at the end of the integer part",
-// "D3089": "The sub-picture must not contain two adjacent instances of the 'grouping-separator' character",
-// "D3090": "The integer part of the sub-picture must not contain a member of the 'decimal digit family' that is followed by an instance of the 'optional digit character'",
-// "D3091": "The fractional part of the sub-picture must not contain an instance of the 'optional digit character' that is followed by a member of the 'decimal digit family'",
-// "D3092": "A sub-picture that contains a 'percent' or 'per-mille' character must not contain a character treated as an 'exponent-separator'",
-// "D3093": "The exponent part of the sub-picture must comprise only of one or more characters that are members of the 'decimal digit family'",
 // "D3100": "The radix of the formatBase function must be between 2 and 36.  It was given {{value}}",
 // "D3110": "The argument of the toMillis function must be an ISO 8601 formatted timestamp. Given {{value}}",
 // "D3120": "Syntax error in expression passed to function eval: {{value}}",
 // "D3121": "Dynamic error evaluating the expression passed to function eval: {{value}}",
 // "D3130": "Formatting or parsing an integer as a sequence starting with {{value}} is not supported by this implementation",
 // "D3131": "In a decimal digit pattern, all digits must be from the same decimal group",
-// "D3132": "Unknown component specifier {{value}} in date/time picture string",
 // "D3133": "The 'name' modifier can only be applied to months and days in the date/time picture string, not {{value}}",
 // "D3134": "The timezone integer format specifier cannot have more than four digits",
 // "D3135": "No matching closing bracket ']' in date/time picture string",
-// "D3136": "The date/time picture string is missing specifiers required to parse the timestamp",
 // "D3138": "The $single() function expected exactly 1 matching result.  Instead it matched more.",
 // "D3139": "The $single() function expected exactly 1 matching result.  Instead it matched 0.",
 // "D3140": "Malformed URL passed to ${{{functionName}}}(): {{value}}",
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constructors() {
+        for (error, code, message) in [
+            (
+                Error::d1002(3, "\"a\""),
+                "D1002",
+                "D1002 @ 3: Cannot negate a non-numeric value `\"a\"`",
+            ),
+            (
+                Error::t0410(0, 2, "sort"),
+                "T0410",
+                "T0410 @ 0: Argument 2 of function sort does not match function signature",
+            ),
+            (
+                Error::t2001(2, "+"),
+                "T2001",
+                "T2001 @ 2: The left side of the `+` operator must evaluate to a number",
+            ),
+            (
+                Error::t2002(2, "*"),
+                "T2002",
+                "T2002 @ 2: The right side of the `*` operator must evaluate to a number",
+            ),
+            (
+                Error::t2009(2, 1, "\"1\"", "<"),
+                "T2009",
+                "T2009 @ 2: The values 1 and \"1\" either side of operator < must be of the same data type",
+            ),
+            (
+                Error::t2010(5, ">="),
+                "T2010",
+                "T2010 @ 5: The expressions either side of operator `>=` must evaluate to numeric or string values",
+            ),
+        ] {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn kinds() {
+        for (error, kind) in [
+            (
+                Error::S0201SyntaxError(0, "x".to_string()),
+                ErrorKind::Static,
+            ),
+            (Error::t0410(0, 1, "sum"), ErrorKind::Type),
+            (Error::D3137Error("x".to_string()), ErrorKind::Dynamic),
+            (Error::U1001Timeout, ErrorKind::Limit),
+            (Error::X0001SortComparatorNotBoolean(0), ErrorKind::Dynamic),
+            (Error::X0002ParentUnsupported(0), ErrorKind::Static),
+            (
+                Error::D3137UserError(0, "x".to_string(), None, None),
+                ErrorKind::Dynamic,
+            ),
+            (
+                Error::D3137UserError(0, "x".to_string(), Some("E".to_string()), None),
+                ErrorKind::User,
+            ),
+        ] {
+            assert_eq!(error.kind(), kind, "{:?}", error);
+        }
+    }
+}
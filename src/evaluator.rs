@@ -1,7 +1,9 @@
+mod expression_cache;
 pub mod frame;
 pub mod functions;
 pub mod value;
 
+use expression_cache::ExpressionCache;
 use frame::Frame;
 use functions::*;
 use value::{ArrayFlags, Value};
@@ -9,7 +11,8 @@ use value::{ArrayFlags, Value};
 use bumpalo::collections::Vec as BumpVec;
 use bumpalo::Bump;
 use std::cell::RefCell;
-use std::collections::{hash_map, HashMap};
+use std::collections::{hash_map, HashMap, HashSet};
+use std::rc::Rc;
 use std::time::Instant;
 
 use super::parser::ast::*;
@@ -26,6 +29,8 @@ pub struct Evaluator<'a> {
     chain_ast: Option<Ast>,
     arena: &'a Bump,
     internal: RefCell<EvaluatorInternal>,
+    expression_cache: RefCell<ExpressionCache>,
+    lenient_comparisons: bool,
 }
 
 impl<'a> Evaluator<'a> {
@@ -44,9 +49,23 @@ impl<'a> Evaluator<'a> {
                 max_depth,
                 time_limit,
             }),
+            expression_cache: RefCell::new(ExpressionCache::new(ExpressionCache::DEFAULT_CAPACITY)),
+            lenient_comparisons: false,
         }
     }
 
+    /// When enabled, ordering operators such as `<` return undefined for operands they can't
+    /// compare, rather than raising T2009 or T2010.
+    pub fn set_lenient_comparisons(&mut self, lenient_comparisons: bool) {
+        self.lenient_comparisons = lenient_comparisons;
+    }
+
+    /// Parses an expression evaluated at runtime (by `$eval`), reusing a previous parse of the
+    /// same source where possible.
+    pub fn parse_cached(&self, source: &str) -> Result<Rc<Ast>> {
+        self.expression_cache.borrow_mut().get_or_parse(source)
+    }
+
     fn fn_context<'e>(
         &'e self,
         name: &'a str,
@@ -101,6 +120,7 @@ impl<'a> Evaluator<'a> {
             AstKind::Bool(b) => Value::bool(b),
             AstKind::String(ref s) => Value::string(self.arena, s),
             AstKind::Number(n) => Value::number(self.arena, n),
+            AstKind::Regex(ref r) => Value::regex(self.arena, r),
             AstKind::Block(ref exprs) => self.evaluate_block(exprs, input, frame)?,
             AstKind::Unary(ref op) => self.evaluate_unary_op(node, op, input, frame)?,
             AstKind::Binary(ref op, ref lhs, ref rhs) => {
@@ -221,13 +241,10 @@ impl<'a> Evaluator<'a> {
                 let result = self.evaluate(value, input, frame)?;
                 match result {
                     Value::Undefined => Ok(Value::undefined()),
-                    Value::Number(n) if result.is_valid_number()? => {
+                    Value::Number(n) if result.is_valid_number(node.char_index)? => {
                         Ok(Value::number(self.arena, -n))
                     }
-                    _ => Err(Error::D1002NegatingNonNumeric(
-                        node.char_index,
-                        result.to_string(),
-                    )),
+                    _ => Err(Error::d1002(node.char_index, result)),
                 }
             }
             UnaryOp::ArrayConstructor(ref array) => {
@@ -294,8 +311,14 @@ impl<'a> Evaluator<'a> {
                 } else {
                     self.evaluate(&pair.0, item, frame)?
                 };
+                // An undefined key is an error too, rather than leaving the pair out
                 if !key.is_string() {
-                    return Err(Error::T1003NonStringKey(char_index, key.to_string()));
+                    let key = if key.is_undefined() {
+                        "undefined".to_string()
+                    } else {
+                        key.serialize(false)
+                    };
+                    return Err(Error::T1003NonStringKey(char_index, key));
                 }
 
                 let key = key.as_str();
@@ -399,37 +422,35 @@ impl<'a> Evaluator<'a> {
             | BinaryOp::Modulus => {
                 let rhs = self.evaluate(rhs_ast, input, frame)?;
 
-                let lhs = if lhs.is_undefined() {
-                    return Ok(Value::undefined());
-                } else if lhs.is_valid_number()? {
-                    lhs.as_f64()
-                } else {
-                    return Err(Error::T2001LeftSideNotNumber(
-                        node.char_index,
-                        op.to_string(),
-                    ));
-                };
+                // Type errors take precedence over undefined operands, so `false + $missing`
+                // and `$missing + false` both fail rather than yielding undefined.
+                if !lhs.is_undefined() && !lhs.is_valid_number(node.char_index)? {
+                    return Err(Error::t2001(node.char_index, op));
+                }
+
+                if !rhs.is_undefined() && !rhs.is_valid_number(node.char_index)? {
+                    return Err(Error::t2002(node.char_index, op));
+                }
 
-                let rhs = if rhs.is_undefined() {
+                if lhs.is_undefined() || rhs.is_undefined() {
                     return Ok(Value::undefined());
-                } else if rhs.is_valid_number()? {
-                    rhs.as_f64()
-                } else {
-                    return Err(Error::T2002RightSideNotNumber(
-                        node.char_index,
-                        op.to_string(),
-                    ));
-                };
+                }
+
+                let lhs = lhs.as_f64();
+                let rhs = rhs.as_f64();
 
                 let result = match op {
                     BinaryOp::Add => lhs + rhs,
                     BinaryOp::Subtract => lhs - rhs,
                     BinaryOp::Multiply => lhs * rhs,
                     BinaryOp::Divide => lhs / rhs,
+                    // Rust's `%` on f64 is a truncated remainder (sign follows the dividend), as in JS
                     BinaryOp::Modulus => lhs % rhs,
                     _ => unreachable!(),
                 };
 
+                // As in JSONata, an infinite result is only an error where it's next used, e.g. as
+                // an operand (D1001) or by `$string` (D3001)
                 Ok(Value::number(self.arena, result))
             }
 
@@ -443,8 +464,14 @@ impl<'a> Evaluator<'a> {
                     return Ok(Value::undefined());
                 }
 
+                if self.lenient_comparisons
+                    && !(lhs.is_number() && rhs.is_number() || lhs.is_string() && rhs.is_string())
+                {
+                    return Ok(Value::undefined());
+                }
+
                 if !((lhs.is_number() || lhs.is_string()) && (rhs.is_number() || rhs.is_string())) {
-                    return Err(Error::T2010BinaryOpTypes(node.char_index, op.to_string()));
+                    return Err(Error::t2010(node.char_index, op));
                 }
 
                 if lhs.is_number() && rhs.is_number() {
@@ -469,12 +496,7 @@ impl<'a> Evaluator<'a> {
                     }));
                 }
 
-                Err(Error::T2009BinaryOpMismatch(
-                    node.char_index,
-                    lhs.to_string(),
-                    rhs.to_string(),
-                    op.to_string(),
-                ))
+                Err(Error::t2009(node.char_index, lhs, rhs, op))
             }
 
             BinaryOp::Equal | BinaryOp::NotEqual => {
@@ -557,12 +579,12 @@ impl<'a> Evaluator<'a> {
                 if let AstKind::Function {
                     ref proc,
                     ref args,
-                    is_partial,
+                    is_partial: false,
                     ..
                 } = rhs_ast.kind
                 {
                     // Function invocation with lhs as the first argument
-                    Ok(self.evaluate_function(input, proc, args, is_partial, frame, Some(lhs))?)
+                    Ok(self.evaluate_function(input, proc, args, false, frame, Some(lhs))?)
                 } else {
                     let rhs = self.evaluate(rhs_ast, input, frame)?;
 
@@ -1029,7 +1051,7 @@ impl<'a> Evaluator<'a> {
         } else {
             ArrayFlags::SEQUENCE
         };
-        let result = Value::array(self.arena, flags);
+        let mut results = BumpVec::new_in(self.arena);
         let input = Value::wrap_in_array_if_needed(self.arena, input, ArrayFlags::empty());
 
         let get_index = |n: f64| {
@@ -1046,17 +1068,13 @@ impl<'a> Evaluator<'a> {
             index as usize
         };
 
+        // An item selected by its index that is itself an array contributes its members to the
+        // sequence, whether the index is a literal or computed, so `$x[0][1]` and `$x[0+0][1]`
+        // agree. Items selected by a boolean predicate are kept whole.
         match predicate.kind {
             AstKind::Number(n) => {
                 let index = get_index(n);
-                let item = input.get_member(index);
-                if !item.is_undefined() {
-                    if item.is_array() {
-                        return Ok(item);
-                    } else {
-                        result.push(item);
-                    }
-                }
+                fn_append_internal(&mut results, input.get_member(index));
             }
             _ => {
                 for (item_index, item) in input.members().enumerate() {
@@ -1067,25 +1085,24 @@ impl<'a> Evaluator<'a> {
                         self.evaluate(predicate, item, frame)?
                     };
 
-                    if index.is_valid_number()? {
+                    if index.is_valid_number(predicate.char_index)? {
                         index = Value::wrap_in_array(self.arena, index, ArrayFlags::empty());
                     }
 
-                    if index.is_array_of_valid_numbers()? {
-                        index.members().for_each(|v| {
-                            let index = get_index(v.as_f64());
-                            if index == item_index {
-                                result.push(item);
+                    if index.is_array_of_valid_numbers(predicate.char_index)? {
+                        for v in index.members() {
+                            if get_index(v.as_f64()) == item_index {
+                                fn_append_internal(&mut results, item);
                             }
-                        });
+                        }
                     } else if index.is_truthy() {
-                        result.push(item);
+                        results.push(item);
                     }
                 }
             }
         }
 
-        Ok(result)
+        Ok(Value::array_from(self.arena, results, flags))
     }
 
     fn evaluate_wildcard(&self, input: &'a Value<'a>) -> Result<&'a Value<'a>> {
@@ -1098,15 +1115,21 @@ impl<'a> Evaluator<'a> {
             input
         };
 
-        if input.is_object() {
-            for (_key, value) in input.entries() {
-                if value.is_array() {
-                    let value = value.flatten(self.arena);
-                    fn_append_internal(&mut values, value);
-                } else {
-                    values.push(value)
-                }
+        let mut push_value = |value: &'a Value<'a>| {
+            if value.is_array() {
+                let value = value.flatten(self.arena);
+                fn_append_internal(&mut values, value);
+            } else {
+                values.push(value)
             }
+        };
+
+        // Arrays are treated like objects keyed by index, so `*` over an array yields its
+        // members. Scalars have no values, so they produce nothing.
+        if input.is_object() {
+            input.entries().for_each(|(_key, value)| push_value(value));
+        } else if input.is_array() {
+            input.members().for_each(push_value);
         }
 
         Ok(Value::array_from(self.arena, values, ArrayFlags::SEQUENCE))
@@ -1114,8 +1137,10 @@ impl<'a> Evaluator<'a> {
 
     fn evaluate_descendants(&self, input: &'a Value<'a>) -> Result<&'a Value<'a>> {
         Ok(if !input.is_undefined() {
-            let result_sequence =
-                self.recurse_descendants(input, Value::array(self.arena, ArrayFlags::SEQUENCE));
+            let result_sequence = Value::array(self.arena, ArrayFlags::SEQUENCE);
+            for descendant in input.descendants() {
+                result_sequence.push(descendant);
+            }
 
             if result_sequence.len() == 1 {
                 result_sequence.get_member(0)
@@ -1127,42 +1152,21 @@ impl<'a> Evaluator<'a> {
         })
     }
 
-    #[allow(clippy::only_used_in_recursion)]
-    fn recurse_descendants(
-        &self,
-        input: &'a Value<'a>,
-        result_sequence: &'a mut Value<'a>,
-    ) -> &'a mut Value<'a> {
-        if !input.is_array() {
-            result_sequence.push(input);
-        }
-
-        let mut result_sequence = result_sequence;
-
-        if input.is_array() {
-            for member in input.members() {
-                result_sequence = self.recurse_descendants(member, result_sequence);
-            }
-        } else if input.is_object() {
-            for (_key, value) in input.entries() {
-                result_sequence = self.recurse_descendants(value, result_sequence);
-            }
-        }
-
-        result_sequence
-    }
-
     fn evaluate_function(
         &self,
         input: &'a Value<'a>,
         proc: &Ast,
         args: &[Ast],
-        _is_partial: bool,
+        is_partial: bool,
         frame: &Frame<'a>,
         context: Option<&'a Value<'a>>,
     ) -> Result<&'a Value<'a>> {
         let evaluated_proc = self.evaluate(proc, input, frame)?;
 
+        if is_partial {
+            return self.partially_apply_function(input, proc, evaluated_proc, args, frame);
+        }
+
         // Help the user out if they forgot a '$'
         if evaluated_proc.is_undefined() {
             if let AstKind::Path(ref steps) = proc.kind {
@@ -1188,15 +1192,103 @@ impl<'a> Evaluator<'a> {
             evaluated_args.push(arg);
         }
 
-        let mut result = self.apply_function(
+        self.apply_function(
             proc.char_index,
             input,
             evaluated_proc,
             &evaluated_args,
             frame,
-        )?;
+        )
+    }
 
-        // Trampoline loop for tail-call optimization
+    /// Partially applying a function produces a lambda whose parameters are the `?` placeholders,
+    /// and whose body calls the original function with the other arguments already bound.
+    fn partially_apply_function(
+        &self,
+        input: &'a Value<'a>,
+        proc: &Ast,
+        evaluated_proc: &'a Value<'a>,
+        args: &[Ast],
+        frame: &Frame<'a>,
+    ) -> Result<&'a Value<'a>> {
+        if !evaluated_proc.is_function() {
+            if let AstKind::Path(ref steps) = proc.kind {
+                if let AstKind::Name(ref name) = steps[0].kind {
+                    if frame.lookup(name).is_some() {
+                        return Err(Error::T1007PartialApplyNonFunctionSuggest(
+                            proc.char_index,
+                            name.clone(),
+                        ));
+                    }
+                }
+            }
+            return Err(Error::T1008PartialApplyNonFunction(proc.char_index));
+        }
+
+        // The bound values live in a frame captured by the lambda, using names that can't
+        // appear in an expression so they never shadow anything.
+        let partial_frame = Frame::new_with_parent(frame);
+        partial_frame.bind("?proc", evaluated_proc);
+
+        let mut bound_args = Vec::with_capacity(args.len());
+        for arg in args {
+            bound_args.push(match arg.kind {
+                AstKind::PartialArg => None,
+                _ => Some(self.evaluate(arg, input, frame)?),
+            });
+        }
+
+        let mut params = Vec::new();
+        let mut call_args = Vec::with_capacity(bound_args.len());
+        for (index, bound_arg) in bound_args.into_iter().enumerate() {
+            let name = format!("?{}", index);
+            let var = Ast::new(AstKind::Var(name.clone()), proc.char_index);
+            match bound_arg {
+                Some(value) => partial_frame.bind(&name, value),
+                None => params.push(var.clone()),
+            }
+            call_args.push(var);
+        }
+
+        let body = Ast::new(
+            AstKind::Function {
+                name: String::from("?proc"),
+                proc: Box::new(Ast::new(
+                    AstKind::Var(String::from("?proc")),
+                    proc.char_index,
+                )),
+                args: call_args,
+                is_partial: false,
+            },
+            proc.char_index,
+        );
+        let lambda = Ast::new(
+            AstKind::Lambda {
+                name: String::from("function"),
+                args: params,
+                body: Box::new(body),
+                thunk: false,
+                signature: None,
+            },
+            proc.char_index,
+        );
+
+        Ok(Value::lambda(self.arena, &lambda, input, partial_frame))
+    }
+
+    pub fn apply_function(
+        &self,
+        char_index: usize,
+        input: &'a Value<'a>,
+        evaluated_proc: &'a Value<'a>,
+        evaluated_args: &[&'a Value<'a>],
+        frame: &Frame<'a>,
+    ) -> Result<&'a Value<'a>> {
+        let mut result =
+            self.apply_function_once(char_index, input, evaluated_proc, evaluated_args, frame)?;
+
+        // Trampoline loop for tail-call optimization. This has to happen here rather than in
+        // evaluate_function, so that lambdas called by native functions don't return thunks.
         // TODO: This loop needs help
         while let Value::Lambda {
             ref ast,
@@ -1223,8 +1315,13 @@ impl<'a> Evaluator<'a> {
                         evaluated_args.push(arg);
                     }
 
-                    result =
-                        self.apply_function(proc.char_index, input, next, &evaluated_args, frame)?;
+                    result = self.apply_function_once(
+                        proc.char_index,
+                        input,
+                        next,
+                        &evaluated_args,
+                        frame,
+                    )?;
                 } else {
                     unreachable!()
                 }
@@ -1236,7 +1333,7 @@ impl<'a> Evaluator<'a> {
         Ok(result)
     }
 
-    pub fn apply_function(
+    fn apply_function_once(
         &self,
         char_index: usize,
         input: &'a Value<'a>,
@@ -1293,6 +1390,20 @@ impl<'a> Evaluator<'a> {
                 let input = &evaluated_args[0];
                 self.apply_transformer(input, pattern, update, delete, frame)
             }
+            Value::Regex(ref regex) => {
+                let input = evaluated_args
+                    .first()
+                    .copied()
+                    .unwrap_or_else(Value::undefined);
+                if !input.is_string() {
+                    return Ok(Value::undefined());
+                }
+                let input = input.as_str();
+                Ok(match regex.captures(&input) {
+                    Some(captures) => regex_match_object(self.arena, &input, &captures),
+                    None => Value::undefined(),
+                })
+            }
             _ => Err(Error::T1006InvokedNonFunction(char_index)),
         }
     }
@@ -1310,24 +1421,37 @@ impl<'a> Evaluator<'a> {
         }
 
         if !input.is_object() && !input.is_array() {
-            return Err(Error::T0410ArgumentNotValid(
-                pattern_ast.char_index,
-                1,
-                "undefined".to_string(),
-            ));
+            return Err(Error::t0410(pattern_ast.char_index, 1, "undefined"));
         }
 
-        let result = input.clone(self.arena);
-
         let matches = self.evaluate(
             pattern_ast,
-            Value::wrap_in_array(self.arena, result, ArrayFlags::empty()),
+            Value::wrap_in_array(self.arena, input, ArrayFlags::empty()),
             frame,
         )?;
 
+        let mut result = input;
+
         if !matches.is_undefined() {
             let matches = Value::wrap_in_array_if_needed(self.arena, matches, ArrayFlags::empty());
+
+            // The input must not be modified, so copy the matches and everything on the way down
+            // to them. Anything else is shared between the input and the result.
+            let targets: HashSet<*const Value<'a>> =
+                matches.members().map(|m| m as *const Value<'a>).collect();
+            let mut copies = HashMap::new();
+            if let Some(copy) = self.copy_to_targets(input, &targets, &mut copies) {
+                result = copy;
+            }
+
             for m in matches.members() {
+                // Matches outside of the input (e.g. from a variable) still get copied, so that
+                // only values allocated here are ever modified below.
+                let m = match copies.get(&(m as *const Value<'a>)) {
+                    Some(copy) => *copy,
+                    None => &*m.clone(self.arena),
+                };
+
                 let update = self.evaluate(update_ast, m, frame)?;
                 if !update.is_undefined() {
                     if !update.is_object() {
@@ -1335,10 +1459,8 @@ impl<'a> Evaluator<'a> {
                             update_ast.char_index,
                             update.to_string(),
                         ));
-                    } else {
-                        for (key, value) in update.entries() {
-                            m.__very_unsafe_make_mut().insert(key, value);
-                        }
+                    } else if m.is_object() {
+                        m.__very_unsafe_make_mut().merge_into(update);
                     }
                 }
 
@@ -1368,4 +1490,62 @@ impl<'a> Evaluator<'a> {
 
         Ok(result)
     }
+
+    /// Returns a copy of `value` if it is, or contains, one of the `targets`. Only the arrays and
+    /// objects along the way to a target are copied; all other members are shared. The copy of
+    /// each target is recorded in `copies`.
+    ///
+    /// This recurses itself rather than using [`Value::descendants`]: each container is copied
+    /// only after its members, which a flat pre-order walk can't tell it about, and arrays, which
+    /// that walk never yields, can be targets too.
+    fn copy_to_targets(
+        &self,
+        value: &'a Value<'a>,
+        targets: &HashSet<*const Value<'a>>,
+        copies: &mut HashMap<*const Value<'a>, &'a Value<'a>>,
+    ) -> Option<&'a Value<'a>> {
+        let ptr = value as *const Value<'a>;
+        if let Some(copy) = copies.get(&ptr) {
+            return Some(copy);
+        }
+
+        let copy: &'a Value<'a> = match value {
+            Value::Array(..) => {
+                let members = value
+                    .members()
+                    .map(|member| (member, self.copy_to_targets(member, targets, copies)))
+                    .collect::<Vec<_>>();
+                if !targets.contains(&ptr) && members.iter().all(|(_, copy)| copy.is_none()) {
+                    return None;
+                }
+                let array =
+                    Value::array_with_capacity(self.arena, members.len(), value.get_flags());
+                for (member, copy) in members {
+                    array.push(copy.unwrap_or(member));
+                }
+                array
+            }
+            Value::Object(..) => {
+                let entries = value
+                    .entries()
+                    .map(|(key, entry)| (key, entry, self.copy_to_targets(entry, targets, copies)))
+                    .collect::<Vec<_>>();
+                if !targets.contains(&ptr) && entries.iter().all(|(_, _, copy)| copy.is_none()) {
+                    return None;
+                }
+                let object = Value::object_with_capacity(self.arena, entries.len());
+                for (key, entry, copy) in entries {
+                    object.insert(key, copy.unwrap_or(entry));
+                }
+                object
+            }
+            _ => return None,
+        };
+
+        if targets.contains(&ptr) {
+            copies.insert(ptr, copy);
+        }
+
+        Some(copy)
+    }
 }
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::parser::{self, ast::Ast};
+use crate::Result;
+
+/// A small least-recently-used cache of parsed expressions, so that `$eval` called repeatedly
+/// with the same string (e.g. once per item in a path) only parses it once.
+pub struct ExpressionCache {
+    capacity: usize,
+    entries: VecDeque<(String, Rc<Ast>)>,
+    parse_count: usize,
+}
+
+impl ExpressionCache {
+    pub const DEFAULT_CAPACITY: usize = 32;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            parse_count: 0,
+        }
+    }
+
+    /// Returns the parsed AST for `source`, parsing it only if it's not already cached. The most
+    /// recently used entries are kept at the back, and the front is evicted when full.
+    pub fn get_or_parse(&mut self, source: &str) -> Result<Rc<Ast>> {
+        if let Some(position) = self.entries.iter().position(|(s, _)| s == source) {
+            let entry = self.entries.remove(position).unwrap();
+            let ast = entry.1.clone();
+            self.entries.push_back(entry);
+            return Ok(ast);
+        }
+
+        let ast = Rc::new(parser::parse(source)?);
+        self.parse_count += 1;
+
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back((source.to_string(), ast.clone()));
+        }
+
+        Ok(ast)
+    }
+
+    /// The number of times an expression has actually been parsed.
+    #[cfg(test)]
+    pub fn parse_count(&self) -> usize {
+        self.parse_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bumpalo::Bump;
+
+    use crate::evaluator::{frame::Frame, functions::fn_eval, Evaluator};
+    use crate::Value;
+
+    #[test]
+    fn reuses_parsed_expressions() {
+        let mut cache = ExpressionCache::new(2);
+        cache.get_or_parse("a.b").unwrap();
+        cache.get_or_parse("a.b").unwrap();
+        cache.get_or_parse("a.b").unwrap();
+        assert_eq!(cache.parse_count(), 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ExpressionCache::new(2);
+        cache.get_or_parse("1").unwrap();
+        cache.get_or_parse("2").unwrap();
+        cache.get_or_parse("1").unwrap();
+        cache.get_or_parse("3").unwrap();
+        assert_eq!(cache.parse_count(), 3);
+
+        // "2" was least recently used, so it was evicted while "1" was kept
+        cache.get_or_parse("1").unwrap();
+        assert_eq!(cache.parse_count(), 3);
+        cache.get_or_parse("2").unwrap();
+        assert_eq!(cache.parse_count(), 4);
+    }
+
+    #[test]
+    fn does_not_cache_errors() {
+        let mut cache = ExpressionCache::new(2);
+        assert!(cache.get_or_parse("1 +").is_err());
+        assert!(cache.get_or_parse("1 +").is_err());
+        assert_eq!(cache.parse_count(), 0);
+    }
+
+    #[test]
+    fn eval_parses_once_per_evaluation() {
+        let arena = Bump::new();
+        let ast = parser::parse("[1..5].$eval('$ * 2')").unwrap();
+        let frame = Frame::new();
+        frame.bind("eval", Value::nativefn(&arena, "eval", 2, fn_eval));
+
+        let evaluator = Evaluator::new(None, &arena, None, None);
+        let result = evaluator
+            .evaluate(&ast, Value::undefined(), &frame)
+            .unwrap();
+
+        assert_eq!(result.serialize(false), "[2,4,6,8,10]");
+        assert_eq!(evaluator.expression_cache.borrow().parse_count(), 1);
+    }
+}
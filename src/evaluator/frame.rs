@@ -45,6 +45,23 @@ impl<'a> Frame<'a> {
             },
         }
     }
+
+    /// Every binding visible from this frame, innermost first, so a name bound in more than one
+    /// frame appears once with the value that [`Frame::lookup`] would find.
+    pub fn bindings(&self) -> Vec<(String, &'a Value<'a>)> {
+        let mut bindings: Vec<(String, &'a Value<'a>)> = Vec::new();
+        let mut frame = Some(self.clone());
+        while let Some(current) = frame {
+            let data = current.0.borrow();
+            for (name, value) in data.bindings.iter() {
+                if !bindings.iter().any(|(bound, _)| bound == name) {
+                    bindings.push((name.clone(), *value));
+                }
+            }
+            frame = data.parent.clone();
+        }
+        bindings
+    }
 }
 
 impl Default for Frame<'_> {
@@ -3,8 +3,12 @@ use chrono::{TimeZone, Utc};
 use rand::Rng;
 use std::borrow::{Borrow, Cow};
 use std::collections::HashSet;
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::datetime::{format_custom_date, parse_custom_format, parse_timezone_offset};
+use crate::datetime::{
+    format_custom_date, parse_custom_format, parse_picture, parse_timezone_offset,
+};
+use crate::number_format::{format_number, DecimalFormat};
 use crate::parser::expressions::check_balanced_brackets;
 
 use bumpalo::collections::CollectIn;
@@ -15,17 +19,13 @@ use crate::{Error, Result};
 
 use super::frame::Frame;
 use super::value::serialize::{DumpFormatter, PrettyFormatter, Serializer};
-use super::value::{ArrayFlags, Value};
+use super::value::{ArrayFlags, FromValue, Value};
 use super::Evaluator;
 
 macro_rules! min_args {
     ($context:ident, $args:ident, $min:literal) => {
         if $args.len() < $min {
-            return Err(Error::T0410ArgumentNotValid(
-                $context.char_index,
-                $min,
-                $context.name.to_string(),
-            ));
+            return Err(Error::t0410($context.char_index, $min, $context.name));
         }
     };
 }
@@ -33,22 +33,14 @@ macro_rules! min_args {
 macro_rules! max_args {
     ($context:ident, $args:ident, $max:literal) => {
         if $args.len() > $max {
-            return Err(Error::T0410ArgumentNotValid(
-                $context.char_index,
-                $max,
-                $context.name.to_string(),
-            ));
+            return Err(Error::t0410($context.char_index, $max, $context.name));
         }
     };
 }
 
 macro_rules! bad_arg {
     ($context:ident, $index:literal) => {
-        return Err(Error::T0410ArgumentNotValid(
-            $context.char_index,
-            $index,
-            $context.name.to_string(),
-        ))
+        return Err(Error::t0410($context.char_index, $index, $context.name))
     };
 }
 
@@ -128,15 +120,10 @@ pub fn fn_append<'a>(
     let arg1_len = if arg1.is_array() { arg1.len() } else { 1 };
     let arg2_len = if arg2.is_array() { arg2.len() } else { 1 };
 
-    let result = Value::array_with_capacity(
-        context.arena,
-        arg1_len + arg2_len,
-        if arg1.is_array() {
-            arg1.get_flags()
-        } else {
-            ArrayFlags::SEQUENCE
-        },
-    );
+    // Like concatenating arrays in the reference implementation, the result is always a plain
+    // array, so a single item isn't unwrapped from it
+    let result =
+        Value::array_with_capacity(context.arena, arg1_len + arg2_len, ArrayFlags::empty());
 
     if arg1.is_array() {
         arg1.members().for_each(|m| result.push(m));
@@ -160,32 +147,12 @@ pub fn fn_boolean<'a>(
     max_args!(context, args, 1);
 
     let arg = args.first().copied().unwrap_or_else(Value::undefined);
-    Ok(match arg {
-        Value::Undefined => Value::undefined(),
-        Value::Null => Value::bool(false),
-        Value::Bool(b) => Value::bool(*b),
-        Value::Number(n) => {
-            arg.is_valid_number()?;
-            Value::bool(*n != 0.0)
-        }
-        Value::String(ref str) => Value::bool(!str.is_empty()),
-        Value::Object(ref obj) => Value::bool(!obj.is_empty()),
-        Value::Array { .. } => match arg.len() {
-            0 => Value::bool(false),
-            1 => fn_boolean(context.clone(), &[arg.get_member(0)])?,
-            _ => {
-                for item in arg.members() {
-                    if fn_boolean(context.clone(), &[item])?.as_bool() {
-                        return Ok(Value::bool(true));
-                    }
-                }
-                Value::bool(false)
-            }
-        },
-        Value::Lambda { .. } | Value::NativeFn { .. } | Value::Transformer { .. } => {
-            Value::bool(false)
-        }
-        Value::Range(ref range) => Value::bool(!range.is_empty()),
+
+    // Shares its rules with conditions and predicates via `is_truthy`
+    Ok(if arg.is_undefined() {
+        Value::undefined()
+    } else {
+        Value::bool(arg.is_truthy())
     })
 }
 
@@ -303,6 +270,9 @@ pub fn fn_each<'a>(
     Ok(result)
 }
 
+/// Returns the keys as a sequence, so an object without keys gives an empty sequence rather than
+/// undefined. Like any empty sequence, that collapses to undefined once the call is evaluated, so
+/// `$keys({})` and `$keys(undefined)` are both undefined and `[$keys({})]` is `[]`.
 pub fn fn_keys<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
@@ -324,23 +294,24 @@ pub fn fn_keys<'a>(
     let mut keys = Vec::new();
 
     if obj.is_array() && obj.members().all(|member| member.is_object()) {
+        // Keys are deduplicated across the objects, keeping the order of first appearance
+        let mut seen = HashSet::new();
         for sub_object in obj.members() {
             for (key, _) in sub_object.entries() {
-                // deduplicating keys from multiple objects
-                if !keys.iter().any(|item| item == key) {
-                    keys.push(key.to_string());
+                if seen.insert(key.as_str()) {
+                    keys.push(key.as_str());
                 }
             }
         }
     } else if obj.is_object() {
         for (key, _) in obj.entries() {
-            keys.push(key.to_string());
+            keys.push(key.as_str());
         }
     }
 
     let result = Value::array(context.arena, ArrayFlags::SEQUENCE);
     for key in keys {
-        result.push(Value::string(context.arena, &key));
+        result.push(Value::string(context.arena, key));
     }
 
     Ok(result)
@@ -349,6 +320,23 @@ pub fn fn_keys<'a>(
 pub fn fn_merge<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    merge_objects(context, args, false)
+}
+
+/// Like `$merge`, but objects found under the same key are merged recursively rather than the
+/// later one replacing the earlier. Any other values, including arrays, are replaced.
+pub fn fn_merge_deep<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    merge_objects(context, args, true)
+}
+
+fn merge_objects<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+    deep: bool,
 ) -> Result<&'a Value<'a>> {
     let mut array_of_objects = if args.is_empty() {
         if context.input.is_array() && context.input.has_flags(ArrayFlags::WRAPPED) {
@@ -378,14 +366,32 @@ pub fn fn_merge<'a>(
     let result = Value::object(context.arena);
 
     for obj in array_of_objects.members() {
-        for (key, value) in obj.entries() {
-            result.insert(key, value);
+        if deep {
+            merge_deep_into(context.arena, result, obj);
+        } else {
+            for (key, value) in obj.entries() {
+                result.insert(key, value);
+            }
         }
     }
 
     Ok(result)
 }
 
+fn merge_deep_into<'a>(arena: &'a Bump, target: &mut Value<'a>, source: &'a Value<'a>) {
+    for (key, value) in source.entries() {
+        let existing = target.get_entry(key);
+        if existing.is_object() && value.is_object() {
+            let merged = Value::object(arena);
+            merge_deep_into(arena, merged, existing);
+            merge_deep_into(arena, merged, value);
+            target.insert(key, merged);
+        } else {
+            target.insert(key, value);
+        }
+    }
+}
+
 pub fn fn_string<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
@@ -416,11 +422,11 @@ pub fn fn_string<'a>(
     } else if input.is_number() && !input.is_finite() {
         Err(Error::D3001StringNotFinite(context.char_index))
     } else if *pretty == true {
-        let serializer = Serializer::new(PrettyFormatter::default(), true);
+        let serializer = Serializer::new(PrettyFormatter::default(), Some(context.char_index));
         let output = serializer.serialize(input)?;
         Ok(Value::string(context.arena, &output))
     } else {
-        let serializer = Serializer::new(DumpFormatter, true);
+        let serializer = Serializer::new(DumpFormatter, Some(context.char_index));
         let output = serializer.serialize(input)?;
         Ok(Value::string(context.arena, &output))
     }
@@ -430,9 +436,22 @@ pub fn fn_substring_before<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
 ) -> Result<&'a Value<'a>> {
-    let string = args.first().copied().unwrap_or_else(Value::undefined);
+    max_args!(context, args, 2);
 
-    let chars = args.get(1).copied().unwrap_or_else(Value::undefined);
+    // With a single argument the string comes from the context, e.g. `url.$substringBefore("/")`
+    let (string, chars) = if args.len() == 1 {
+        let input = if context.input.is_array() && context.input.has_flags(ArrayFlags::WRAPPED) {
+            &context.input[0]
+        } else {
+            context.input
+        };
+        (input, args[0])
+    } else {
+        (
+            args.first().copied().unwrap_or_else(Value::undefined),
+            args.get(1).copied().unwrap_or_else(Value::undefined),
+        )
+    };
 
     if !string.is_string() {
         return Ok(Value::undefined());
@@ -456,9 +475,22 @@ pub fn fn_substring_after<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
 ) -> Result<&'a Value<'a>> {
-    let string = args.first().copied().unwrap_or_else(Value::undefined);
+    max_args!(context, args, 2);
 
-    let chars = args.get(1).copied().unwrap_or_else(Value::undefined);
+    // With a single argument the string comes from the context, e.g. `url.$substringAfter("/")`
+    let (string, chars) = if args.len() == 1 {
+        let input = if context.input.is_array() && context.input.has_flags(ArrayFlags::WRAPPED) {
+            &context.input[0]
+        } else {
+            context.input
+        };
+        (input, args[0])
+    } else {
+        (
+            args.first().copied().unwrap_or_else(Value::undefined),
+            args.get(1).copied().unwrap_or_else(Value::undefined),
+        )
+    };
 
     if !string.is_string() {
         return Ok(Value::undefined());
@@ -551,6 +583,40 @@ pub fn fn_trim<'a>(
 pub fn fn_substring<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    // NOTE: Chars are not grapheme clusters, so for some inputs like "नमस्ते" we will get 6
+    //       as it will include the diacritics. `$substringGraphemes` counts clusters instead.
+    //       See: https://doc.rust-lang.org/nightly/book/ch08-02-strings.html
+    substring_of_units(
+        context,
+        args,
+        |string| string.chars().count(),
+        |string, n| string.char_indices().nth(n).map(|(index, _)| index),
+    )
+}
+
+/// Like `$substring`, but counts extended grapheme clusters rather than characters, so that
+/// e.g. an emoji joined from several characters is never split.
+pub fn fn_substring_graphemes<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    substring_of_units(
+        context,
+        args,
+        |string| string.graphemes(true).count(),
+        |string, n| string.grapheme_indices(true).nth(n).map(|(index, _)| index),
+    )
+}
+
+/// Implements `$substring` over the units of a string, so that the start and length count those
+/// units. `count` gives the number of units in a string, and `offset` the byte index of its nth
+/// unit, if it has one.
+fn substring_of_units<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+    count: impl Fn(&str) -> usize,
+    offset: impl Fn(&str, usize) -> Option<usize>,
 ) -> Result<&'a Value<'a>> {
     let string = args.first().copied().unwrap_or_else(Value::undefined);
     let start = args.get(1).copied().unwrap_or_else(Value::undefined);
@@ -565,23 +631,17 @@ pub fn fn_substring<'a>(
 
     let string = string.as_str();
 
-    // Scan the string chars for the actual number of characters.
-    // NOTE: Chars are not grapheme clusters, so for some inputs like "नमस्ते" we will get 6
-    //       as it will include the diacritics.
-    //       See: https://doc.rust-lang.org/nightly/book/ch08-02-strings.html
-    let len = string.chars().count() as isize;
+    // A negative start counts from the end of the string, but never runs off the front of it
     let mut start = start.as_isize();
-
-    // If start is negative and runs off the front of the string
-    if len + start < 0 {
-        start = 0;
+    if start < 0 {
+        start = (count(&string) as isize + start).max(0);
     }
 
-    // If start is negative, count from the end of the string
-    let start = if start < 0 { len + start } else { start };
+    let from = offset(&string, start as usize).unwrap_or(string.len());
+    let rest = &string[from..];
 
     if length.is_undefined() {
-        Ok(Value::string(context.arena, &string[start as usize..]))
+        Ok(Value::string(context.arena, rest))
     } else {
         assert_arg!(length.is_number(), context, 3);
 
@@ -589,21 +649,78 @@ pub fn fn_substring<'a>(
         if length < 0 {
             Ok(Value::string(context.arena, ""))
         } else {
-            let end = if start >= 0 {
-                (start + length) as usize
-            } else {
-                (len + start + length) as usize
-            };
+            let to = offset(rest, length as usize).unwrap_or(rest.len());
+            Ok(Value::string(context.arena, &rest[..to]))
+        }
+    }
+}
+
+/// Builds the object describing a regex match, as passed to a `$replace` replacer function:
+/// the matched text, its char index, and the text of each capture group.
+pub fn regex_match_object<'a>(
+    arena: &'a Bump,
+    input: &str,
+    captures: &regex::Captures,
+) -> &'a Value<'a> {
+    let whole = captures.get(0).unwrap();
+    let index = input[..whole.start()].chars().count();
+
+    let groups = Value::array(arena, ArrayFlags::empty());
+    for group in captures.iter().skip(1) {
+        groups.push(Value::string(arena, group.map_or("", |g| g.as_str())));
+    }
+
+    let result = Value::object(arena);
+    result.insert("match", Value::string(arena, whole.as_str()));
+    result.insert("index", Value::number(arena, index as f64));
+    result.insert("groups", groups);
+    result
+}
+
+/// Expands the `$` references in a replacement string: `$$` is a literal `$`, and `$N` is the
+/// text of capture group N (with `$0` being the whole match). As in JavaScript, `$12` refers to
+/// group 1 followed by `2` when there are fewer than 12 groups.
+fn expand_replacement(replacement: &str, captures: &regex::Captures) -> String {
+    let groups = captures.len() - 1;
+    let max_digits = if groups == 0 {
+        1
+    } else {
+        (groups as f64).log10().floor() as usize + 1
+    };
+
+    let mut result = String::new();
+    let mut rest = replacement;
+    while let Some(position) = rest.find('$') {
+        result.push_str(&rest[..position]);
+        rest = &rest[position + 1..];
 
-            let substring = string
-                .chars()
-                .skip(start as usize)
-                .take(end - start as usize)
-                .collect::<String>();
+        if let Some(rest_after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = rest_after;
+            continue;
+        }
 
-            Ok(Value::string(context.arena, &substring))
+        let digits = rest
+            .chars()
+            .take(max_digits)
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            result.push('$');
+            continue;
         }
+
+        let mut index: usize = rest[..digits].parse().unwrap();
+        if index > groups && digits > 1 {
+            index /= 10;
+        }
+        if index <= groups {
+            result.push_str(captures.get(index).map_or("", |g| g.as_str()));
+        }
+        rest = &rest[index.to_string().len()..];
     }
+    result.push_str(rest);
+    result
 }
 
 pub fn fn_contains<'a>(
@@ -618,12 +735,18 @@ pub fn fn_contains<'a>(
     }
 
     assert_arg!(str_value.is_string(), context, 1);
-    assert_arg!(token_value.is_string(), context, 2);
 
     let str_value = str_value.as_str();
-    let token_value = token_value.as_str();
 
-    Ok(Value::bool(str_value.contains(&token_value.to_string())))
+    let contains = match token_value {
+        Value::Regex(ref regex) => regex.is_match(&str_value),
+        _ => {
+            assert_arg!(token_value.is_string(), context, 2);
+            str_value.contains(&*token_value.as_str())
+        }
+    };
+
+    Ok(Value::bool(contains))
 }
 
 pub fn fn_replace<'a>(
@@ -644,12 +767,12 @@ pub fn fn_replace<'a>(
     }
 
     assert_arg!(str_value.is_string(), context, 1);
-    assert_arg!(pattern_value.is_string(), context, 2);
-    assert_arg!(replacement_value.is_string(), context, 3);
+    assert_arg!(
+        pattern_value.is_string() || pattern_value.is_regex(),
+        context,
+        2
+    );
 
-    let str_value = str_value.as_str();
-    let pattern_value = pattern_value.as_str();
-    let replacement_value = replacement_value.as_str();
     let limit_value = if limit_value.is_undefined() {
         None
     } else {
@@ -660,6 +783,50 @@ pub fn fn_replace<'a>(
         Some(limit_value.as_isize())
     };
 
+    if let Value::Regex(ref regex) = pattern_value {
+        assert_arg!(
+            replacement_value.is_string() || replacement_value.is_function(),
+            context,
+            3
+        );
+        let str_value = str_value.as_str();
+        let limit = limit_value.map_or(usize::MAX, |limit| limit as usize);
+
+        let mut result = String::new();
+        let mut last = 0;
+        for captures in regex.captures_iter(&str_value).take(limit) {
+            let whole = captures.get(0).unwrap();
+            if whole.as_str().is_empty() {
+                return Err(Error::D1004ZeroLengthMatch(context.char_index));
+            }
+
+            result.push_str(&str_value[last..whole.start()]);
+            if replacement_value.is_string() {
+                result.push_str(&expand_replacement(&replacement_value.as_str(), &captures));
+            } else {
+                let match_object = regex_match_object(context.arena, &str_value, &captures);
+                let replaced = context.evaluate_function(replacement_value, &[match_object])?;
+                if !replaced.is_string() {
+                    return Err(Error::D3012InvalidReplacementType(
+                        context.char_index,
+                        replaced.to_string(),
+                    ));
+                }
+                result.push_str(&replaced.as_str());
+            }
+            last = whole.end();
+        }
+        result.push_str(&str_value[last..]);
+
+        return Ok(Value::string(context.arena, &result));
+    }
+
+    assert_arg!(replacement_value.is_string(), context, 3);
+
+    let str_value = str_value.as_str();
+    let pattern_value = pattern_value.as_str();
+    let replacement_value = replacement_value.as_str();
+
     let replaced_string = if let Some(limit) = limit_value {
         str_value.replacen(
             &pattern_value.to_string(),
@@ -673,6 +840,46 @@ pub fn fn_replace<'a>(
     Ok(Value::string(context.arena, &replaced_string))
 }
 
+pub fn fn_match<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    let str_value = args.first().copied().unwrap_or_else(Value::undefined);
+    let pattern_value = args.get(1).copied().unwrap_or_else(Value::undefined);
+    let limit_value = args.get(2).copied().unwrap_or_else(Value::undefined);
+
+    if str_value.is_undefined() {
+        return Ok(Value::undefined());
+    }
+
+    assert_arg!(str_value.is_string(), context, 1);
+    let Value::Regex(ref regex) = pattern_value else {
+        bad_arg!(context, 2);
+    };
+
+    let limit = if limit_value.is_undefined() {
+        usize::MAX
+    } else {
+        assert_arg!(limit_value.is_number(), context, 3);
+        if limit_value.as_isize().is_negative() {
+            return Err(Error::D3040NegativeLimit(context.char_index));
+        }
+        limit_value.as_isize() as usize
+    };
+
+    // Each match is a plain object, so the result can be serialized like any other value
+    let str_value = str_value.as_str();
+    let result = Value::array(context.arena, ArrayFlags::SEQUENCE);
+    for captures in regex.captures_iter(&str_value).take(limit) {
+        if captures.get(0).unwrap().as_str().is_empty() {
+            return Err(Error::D1004ZeroLengthMatch(context.char_index));
+        }
+        result.push(regex_match_object(context.arena, &str_value, &captures));
+    }
+
+    Ok(result)
+}
+
 pub fn fn_split<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
@@ -686,10 +893,13 @@ pub fn fn_split<'a>(
     }
 
     assert_arg!(str_value.is_string(), context, 1);
-    assert_arg!(separator_value.is_string(), context, 2);
+    assert_arg!(
+        separator_value.is_string() || separator_value.is_regex(),
+        context,
+        2
+    );
 
     let str_value = str_value.as_str();
-    let separator_value = separator_value.as_str();
     let limit_value = if limit_value.is_undefined() {
         None
     } else {
@@ -700,13 +910,14 @@ pub fn fn_split<'a>(
         Some(limit_value.as_isize())
     };
 
-    let substrings: Vec<&str> = if let Some(limit) = limit_value {
-        str_value
-            .split(&separator_value.to_string())
-            .take(limit as usize)
-            .collect()
-    } else {
-        str_value.split(&separator_value.to_string()).collect()
+    // Only the text between matches is kept, never a regex's capture groups
+    let limit = limit_value.map_or(usize::MAX, |limit| limit as usize);
+    let substrings: Vec<&str> = match separator_value {
+        Value::Regex(ref regex) => regex.split(&str_value).take(limit).collect(),
+        _ => str_value
+            .split(&*separator_value.as_str())
+            .take(limit)
+            .collect(),
     };
 
     let substrings_count = substrings.len();
@@ -832,11 +1043,9 @@ pub fn fn_max<'a>(
         return Ok(Value::undefined());
     }
 
-    let arr = Value::wrap_in_array_if_needed(context.arena, arg, ArrayFlags::empty());
-
     let mut max = f64::MIN;
 
-    for member in arr.members() {
+    for member in arg.iter() {
         assert_array_of_type!(member.is_number(), context, 1, "number");
         max = f64::max(max, member.as_f64());
     }
@@ -856,17 +1065,55 @@ pub fn fn_min<'a>(
         return Ok(Value::undefined());
     }
 
-    let arr = Value::wrap_in_array_if_needed(context.arena, arg, ArrayFlags::empty());
-
     let mut min = f64::MAX;
 
-    for member in arr.members() {
+    for member in arg.iter() {
         assert_array_of_type!(member.is_number(), context, 1, "number");
         min = f64::min(min, member.as_f64());
     }
     Ok(Value::number(context.arena, min))
 }
 
+/// The lexicographically greatest string in the array, as a string counterpart of `$max`.
+pub fn fn_max_string<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 1);
+
+    let arg = args.first().copied().unwrap_or_else(Value::undefined);
+
+    let mut max: Option<&'a Value<'a>> = None;
+    for member in arg.iter() {
+        assert_array_of_type!(member.is_string(), context, 1, "string");
+        match max {
+            Some(max) if member.as_str() <= max.as_str() => (),
+            _ => max = Some(member),
+        }
+    }
+    Ok(max.unwrap_or_else(Value::undefined))
+}
+
+/// The lexicographically least string in the array, as a string counterpart of `$min`.
+pub fn fn_min_string<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 1);
+
+    let arg = args.first().copied().unwrap_or_else(Value::undefined);
+
+    let mut min: Option<&'a Value<'a>> = None;
+    for member in arg.iter() {
+        assert_array_of_type!(member.is_string(), context, 1, "string");
+        match min {
+            Some(min) if member.as_str() >= min.as_str() => (),
+            _ => min = Some(member),
+        }
+    }
+    Ok(min.unwrap_or_else(Value::undefined))
+}
+
 pub fn fn_sum<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
@@ -880,15 +1127,33 @@ pub fn fn_sum<'a>(
         return Ok(Value::undefined());
     }
 
-    let arr = Value::wrap_in_array_if_needed(context.arena, arg, ArrayFlags::empty());
-
+    // Neumaier's variant of Kahan summation, which keeps track of the low-order bits lost
+    // by each addition so that long or wide-ranging inputs don't drift.
     let mut sum = 0.0;
+    let mut compensation = 0.0;
 
-    for member in arr.members() {
+    for member in arg.iter() {
         assert_array_of_type!(member.is_number(), context, 1, "number");
-        sum += member.as_f64();
+        let n = member.as_f64();
+        let t = sum + n;
+
+        // Once the sum overflows the compensation would become NaN, so report the overflow itself
+        if !t.is_finite() {
+            return Err(Error::D1001NumberOfOutRange(context.char_index, t));
+        }
+
+        if f64::abs(sum) >= f64::abs(n) {
+            compensation += (sum - t) + n;
+        } else {
+            compensation += (n - t) + sum;
+        }
+        sum = t;
     }
-    Ok(Value::number(context.arena, sum))
+    Ok(Value::finite_number(
+        context.arena,
+        sum + compensation,
+        context.char_index,
+    )?)
 }
 
 pub fn fn_number<'a>(
@@ -905,15 +1170,17 @@ pub fn fn_number<'a>(
         Value::Bool(true) => Ok(Value::number(context.arena, 1)),
         Value::Bool(false) => Ok(Value::number(context.arena, 0)),
         Value::String(s) => {
+            // Surrounding whitespace is ignored, but an empty or blank string isn't a number
             let result: f64 = s
+                .trim()
                 .parse()
                 .map_err(|_e| Error::D3030NonNumericCast(context.char_index, arg.to_string()))?;
 
-            if !result.is_nan() && !result.is_infinite() {
-                Ok(Value::number(context.arena, result))
-            } else {
-                Ok(Value::undefined())
-            }
+            Ok(
+                Value::finite_number(context.arena, result, context.char_index).map_err(|_e| {
+                    Error::D3030NonNumericCast(context.char_index, arg.to_string())
+                })?,
+            )
         }
         _ => bad_arg!(context, 1),
     }
@@ -937,12 +1204,16 @@ pub fn fn_now<'a>(
 
     let now = Utc::now();
 
-    let (picture, timezone) = match args {
-        [picture, timezone] => (picture.as_str(), timezone.as_str()),
-        [picture] => (picture.as_str(), Cow::Borrowed("")),
-        [] => (Cow::Borrowed(""), Cow::Borrowed("")),
-        _ => return Ok(Value::string(context.arena, "")),
-    };
+    // Both arguments are optional strings, and an undefined one counts as absent
+    let mut strings = [Cow::Borrowed(""), Cow::Borrowed("")];
+    for (index, arg) in args.iter().enumerate() {
+        if arg.is_string() {
+            strings[index] = arg.as_str();
+        } else if !arg.is_undefined() {
+            return Err(Error::t0410(context.char_index, index + 1, context.name));
+        }
+    }
+    let [picture, timezone] = strings;
 
     if picture.is_empty() && timezone.is_empty() {
         return Ok(Value::string(
@@ -954,7 +1225,7 @@ pub fn fn_now<'a>(
     let adjusted_time = if !timezone.is_empty() {
         parse_timezone_offset(&timezone)
             .map(|offset| now.with_timezone(&offset))
-            .ok_or_else(|| Error::T0410ArgumentNotValid(2, 1, context.name.to_string()))?
+            .ok_or_else(|| Error::t0410(context.char_index, 2, context.name))?
     } else {
         now.into()
     };
@@ -985,6 +1256,84 @@ pub fn fn_exists<'a>(
     }
 }
 
+pub fn fn_eval<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    min_args!(context, args, 1);
+    max_args!(context, args, 2);
+
+    let expr = args[0];
+
+    if expr.is_undefined() {
+        return Ok(Value::undefined());
+    }
+
+    assert_arg!(expr.is_string(), context, 1);
+
+    let ast = context
+        .evaluator
+        .parse_cached(&expr.as_str())
+        .map_err(|e| Error::D3120EvalSyntaxError(context.char_index, e.to_string()))?;
+
+    let input: &'a Value<'a> = match args.get(1) {
+        Some(input) if input.is_array() && !input.has_flags(ArrayFlags::SEQUENCE) => {
+            Value::wrap_in_array(context.arena, input, ArrayFlags::WRAPPED)
+        }
+        Some(input) => input,
+        None => context.input,
+    };
+
+    context
+        .evaluator
+        .evaluate(&ast, input, &context.frame)
+        .map_err(|e| Error::D3121EvalDynamicError(context.char_index, e.to_string()))
+}
+
+/// Non-standard, and only bound when enabled with [`crate::JsonAta::set_environment_function`].
+/// Returns an object of the variables visible where it's called, leaving out functions and the
+/// `$` root context.
+pub fn fn_environment<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 0);
+
+    let mut bindings = context.frame.bindings();
+    bindings.retain(|(name, value)| name != "$" && !value.is_function());
+
+    let result = Value::object_with_capacity(context.arena, bindings.len());
+    for (name, value) in bindings {
+        result.insert(&name, value);
+    }
+    Ok(result)
+}
+
+pub fn fn_type<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 1);
+
+    let arg = args.first().copied().unwrap_or_else(Value::undefined);
+
+    let type_name = match arg {
+        Value::Undefined => return Ok(Value::undefined()),
+        Value::Null => "null",
+        Value::Number(..) => "number",
+        Value::Bool(..) => "boolean",
+        Value::String(..) => "string",
+        Value::Array(..) | Value::Range(..) => "array",
+        Value::Object(..) => "object",
+        Value::Lambda { .. }
+        | Value::NativeFn { .. }
+        | Value::Transformer { .. }
+        | Value::Regex(..) => "function",
+    };
+
+    Ok(Value::string(context.arena, type_name))
+}
+
 pub fn from_millis<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
@@ -996,12 +1345,13 @@ pub fn from_millis<'a>(
     }
 
     max_args!(context, args, 3);
-    let millis = args[0].as_f64() as i64;
+    assert_arg!(arr.is_number(), context, 1);
+    let millis = arr.as_f64() as i64;
 
     let timestamp = Utc
         .timestamp_millis_opt(millis)
         .single()
-        .ok_or_else(|| Error::T0410ArgumentNotValid(0, 1, context.name.to_string()))?;
+        .ok_or_else(|| Error::t0410(context.char_index, 1, context.name))?;
 
     let (picture, timezone) = match args {
         [_, picture, timezone] => (
@@ -1010,7 +1360,11 @@ pub fn from_millis<'a>(
             } else {
                 Cow::Borrowed("") // Treat non-strings (like ()) as empty strings
             },
-            timezone.as_str(),
+            if timezone.is_string() {
+                timezone.as_str()
+            } else {
+                Cow::Borrowed("")
+            },
         ),
         [_, picture] => (
             if picture.is_string() {
@@ -1039,7 +1393,7 @@ pub fn from_millis<'a>(
     let adjusted_time = if !timezone.is_empty() {
         parse_timezone_offset(&timezone)
             .map(|offset| timestamp.with_timezone(&offset))
-            .ok_or_else(|| Error::T0410ArgumentNotValid(0, 1, context.name.to_string()))?
+            .ok_or_else(|| Error::t0410(context.char_index, 3, context.name))?
     } else {
         timestamp.into()
     };
@@ -1083,8 +1437,15 @@ pub fn to_millis<'a>(
         _ => Cow::Borrowed(""),
     };
 
-    // Handle different formats using a match handler function
-    match parse_custom_format(&timestamp_str, &picture) {
+    // Handle different formats using a match handler function, falling back to reading the
+    // picture's components one by one
+    let millis = match parse_custom_format(&timestamp_str, &picture) {
+        Some(millis) => Some(millis),
+        None if !picture.is_empty() => parse_picture(&timestamp_str, &picture)?,
+        None => None,
+    };
+
+    match millis {
         Some(millis) => Ok(Value::number(context.arena, millis as f64)),
         None => Ok(Value::undefined()),
     }
@@ -1131,8 +1492,7 @@ pub fn fn_zip<'a>(
         .collect();
 
     // Use an iterator of zipping all the array iterators and collect the result in bumpalo
-    let result: bumpalo::collections::Vec<&Value<'a>> = std::iter::repeat(())
-        .take(min_length)
+    let result: bumpalo::collections::Vec<&Value<'a>> = std::iter::repeat_n((), min_length)
         .map(|_| {
             let zipped: bumpalo::collections::Vec<&Value<'a>> = iterators
                 .iter_mut()
@@ -1154,6 +1514,48 @@ pub fn fn_zip<'a>(
     ))
 }
 
+/// Like `$zip`, but continues to the end of the longest array rather than the shortest. The first
+/// argument is the value that fills in for the members of shorter arrays, and if it's undefined
+/// they're left out instead. It always comes first, so that it can't be mistaken for an array to
+/// zip, e.g. `$zipLongest(0, [1, 2], [3])` is `[[1, 3], [2, 0]]`.
+pub fn fn_zip_longest<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    min_args!(context, args, 2);
+
+    let (pad, arrays) = args.split_first().unwrap();
+    let arrays: Vec<&'a Value<'a>> = arrays
+        .iter()
+        .map(|arg| {
+            if arg.is_undefined() {
+                Value::array(context.arena, ArrayFlags::empty())
+            } else {
+                Value::wrap_in_array_if_needed(context.arena, arg, ArrayFlags::empty())
+            }
+        })
+        .collect();
+
+    let max_length = arrays.iter().map(|arr| arr.len()).max().unwrap_or(0);
+    let result = Value::array_with_capacity(context.arena, max_length, ArrayFlags::empty());
+    for index in 0..max_length {
+        let tuple = Value::array_with_capacity(context.arena, arrays.len(), ArrayFlags::empty());
+        for arr in arrays.iter() {
+            let member = if index < arr.len() {
+                arr.get_member(index)
+            } else {
+                pad
+            };
+            if !member.is_undefined() {
+                tuple.push(member);
+            }
+        }
+        result.push(tuple);
+    }
+
+    Ok(result)
+}
+
 pub fn single<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
@@ -1180,7 +1582,7 @@ pub fn single<'a>(
 
     if !arr.is_array() {
         let res = context.evaluate_function(func, &[arr])?;
-        return if res.as_bool() {
+        return if res.is_truthy() {
             Ok(arr)
         } else {
             Err(Error::D3139Error(
@@ -1198,7 +1600,7 @@ pub fn single<'a>(
                 &[entry, Value::number(context.arena, index as f64), arr],
             )?;
 
-            if res.as_bool() {
+            if res.is_truthy() {
                 if result.is_some() {
                     return Err(Error::D3138Error(format!(
                         "More than one value matched the predicate at index {}",
@@ -1212,7 +1614,7 @@ pub fn single<'a>(
 
         result.ok_or_else(|| Error::D3139Error("No values matched the predicate.".to_string()))
     } else {
-        Err(Error::T0410ArgumentNotValid(0, 2, context.name.to_string()))
+        Err(Error::t0410(context.char_index, 2, context.name))
     }
 }
 
@@ -1220,17 +1622,24 @@ pub fn fn_assert<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
 ) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 2);
+
     let condition = args.first().copied().unwrap_or_else(Value::undefined);
     let message = args.get(1).copied().unwrap_or_else(Value::undefined);
 
+    // Both arguments are checked up front, so a bad message is caught even if the assertion holds
     assert_arg!(condition.is_bool(), context, 1);
+    assert_arg!(message.is_undefined() || message.is_string(), context, 2);
 
     if let Value::Bool(false) = condition {
-        Err(Error::D3141Assert(if message.is_string() {
-            message.as_str().to_string()
-        } else {
-            "$assert() statement failed".to_string()
-        }))
+        Err(Error::D3141Assert(
+            context.char_index,
+            if message.is_string() {
+                message.as_str().to_string()
+            } else {
+                "$assert() statement failed".to_string()
+            },
+        ))
     } else {
         Ok(Value::undefined())
     }
@@ -1240,15 +1649,36 @@ pub fn fn_error<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
 ) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 3);
+
     let message = args.first().copied().unwrap_or_else(Value::undefined);
+    let code = args.get(1).copied().unwrap_or_else(Value::undefined);
+    let data = args.get(2).copied().unwrap_or_else(Value::undefined);
 
     assert_arg!(message.is_undefined() || message.is_string(), context, 1);
+    assert_arg!(
+        code.is_undefined() || (code.is_string() && !code.as_str().is_empty()),
+        context,
+        2
+    );
+    assert_arg!(data.is_undefined() || data.is_object(), context, 3);
 
-    Err(Error::D3137Error(if message.is_string() {
+    let message = if message.is_string() {
         message.as_str().to_string()
     } else {
         "$error() function evaluated".to_string()
-    }))
+    };
+
+    // A code or data makes a structured error, which the host can match on
+    if code.is_undefined() && data.is_undefined() {
+        return Err(Error::D3137Error(message));
+    }
+    Err(Error::D3137UserError(
+        context.char_index,
+        message,
+        code.try_as_str().map(String::from),
+        serde_json::Value::from_value(data),
+    ))
 }
 
 pub fn fn_length<'a>(
@@ -1310,15 +1740,11 @@ pub fn fn_power<'a>(
 
     let result = number.as_f64().powf(exp.as_f64());
 
-    if !result.is_finite() {
-        Err(Error::D3061PowUnrepresentable(
-            context.char_index,
-            number.to_string(),
-            exp.to_string(),
-        ))
-    } else {
-        Ok(Value::number(context.arena, result))
-    }
+    Ok(
+        Value::finite_number(context.arena, result, context.char_index).map_err(|_e| {
+            Error::D3061PowUnrepresentable(context.char_index, number.to_string(), exp.to_string())
+        })?,
+    )
 }
 
 pub fn fn_reverse<'a>(
@@ -1340,6 +1766,35 @@ pub fn fn_reverse<'a>(
     Ok(result)
 }
 
+pub fn fn_first<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 1);
+
+    let arr = args.first().copied().unwrap_or_else(Value::undefined);
+
+    // Same as `arr[0]`, a single value is treated as an array of one
+    let arr = Value::wrap_in_array_if_needed(context.arena, arr, ArrayFlags::empty());
+    Ok(arr.index(0))
+}
+
+pub fn fn_last<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 1);
+
+    let arr = args.first().copied().unwrap_or_else(Value::undefined);
+
+    // Same as `arr[-1]`, a single value is treated as an array of one
+    let arr = Value::wrap_in_array_if_needed(context.arena, arr, ArrayFlags::empty());
+    Ok(match arr.len().checked_sub(1) {
+        Some(index) => arr.index(index),
+        None => Value::undefined(),
+    })
+}
+
 #[allow(clippy::mutable_key_type)]
 pub fn fn_distinct<'a>(
     context: FunctionContext<'a, '_>,
@@ -1414,11 +1869,22 @@ pub fn fn_sort<'a, 'e>(
     max_args!(context, args, 2);
 
     let arr = args.first().copied().unwrap_or_else(Value::undefined);
+    let comparator = args.get(1).copied();
 
     if arr.is_undefined() {
         return Ok(Value::undefined());
     }
 
+    // The comparator is given two items, and returns whether the first should come after the
+    // second. Any parameters after those two are left undefined, as in JSONata.
+    if let Some(comparator) = comparator {
+        assert_arg!(
+            comparator.is_function() && comparator.arity() >= 2,
+            context,
+            2
+        );
+    }
+
     if !arr.is_array() || arr.len() <= 1 {
         return Ok(Value::wrap_in_array_if_needed(
             context.arena,
@@ -1431,22 +1897,23 @@ pub fn fn_sort<'a, 'e>(
     // at least it's just references.
 
     let unsorted = arr.members().collect::<Vec<&'a Value<'a>>>();
-    let sorted = if args.get(1).is_none() {
-        merge_sort(
+    let sorted = match comparator {
+        None => merge_sort(
             unsorted,
+            // Numbers are always finite, so there's no NaN to make this comparison inconsistent
             &|a: &'a Value<'a>, b: &'a Value<'a>| match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(a > b),
                 (Value::String(a), Value::String(b)) => Ok(a > b),
                 _ => Err(Error::D3070InvalidDefaultSort(context.char_index)),
             },
-        )?
-    } else {
-        let comparator = args.get(1).copied().unwrap_or_else(Value::undefined);
-        assert_arg!(comparator.is_function(), context, 2);
-        merge_sort(unsorted, &|a: &'a Value<'a>, b: &'a Value<'a>| {
-            let result = context.evaluate_function(comparator, &[a, b])?;
-            Ok(result.is_truthy())
-        })?
+        )?,
+        Some(comparator) => merge_sort(unsorted, &|a: &'a Value<'a>, b: &'a Value<'a>| {
+            let swap = context.evaluate_function(comparator, &[a, b])?;
+            match swap {
+                Value::Bool(swap) => Ok(*swap),
+                _ => Err(Error::X0001SortComparatorNotBoolean(context.char_index)),
+            }
+        })?,
     };
 
     let result = Value::array_with_capacity(context.arena, sorted.len(), arr.get_flags());
@@ -1455,6 +1922,8 @@ pub fn fn_sort<'a, 'e>(
     Ok(result)
 }
 
+/// Sorts `items` using `comp`, which returns true if its first argument should come after its
+/// second. The sort is stable, so items that `comp` considers equal keep their input order.
 pub fn merge_sort<'a, F>(items: Vec<&'a Value<'a>>, comp: &F) -> Result<Vec<&'a Value<'a>>>
 where
     F: Fn(&'a Value<'a>, &'a Value<'a>) -> Result<bool>,
@@ -1475,6 +1944,7 @@ where
             result.extend(left);
             Ok(())
         } else if comp(left[0], right[0])? {
+            // Only take from the right when it's strictly smaller, which keeps the sort stable
             result.push(right[0]);
             merge_iter(result, left, &right[1..], comp)
         } else {
@@ -1544,6 +2014,42 @@ pub fn fn_base64_decode<'a>(
     Ok(Value::string(context.arena, &decoded))
 }
 
+pub fn fn_format_number<'a>(
+    context: FunctionContext<'a, '_>,
+    args: &[&'a Value<'a>],
+) -> Result<&'a Value<'a>> {
+    max_args!(context, args, 3);
+
+    let number = args.first().copied().unwrap_or_else(Value::undefined);
+    if number.is_undefined() {
+        return Ok(Value::undefined());
+    }
+
+    let picture = args.get(1).copied().unwrap_or_else(Value::undefined);
+    let options = args.get(2).copied().unwrap_or_else(Value::undefined);
+
+    assert_arg!(number.is_number(), context, 1);
+    assert_arg!(picture.is_string(), context, 2);
+
+    let mut format = DecimalFormat::default();
+    if !options.is_undefined() {
+        assert_arg!(options.is_object(), context, 3);
+        for (name, value) in options.entries() {
+            assert_arg!(value.is_string(), context, 3);
+            format.set(name, &value.as_str());
+        }
+    }
+
+    let formatted = format_number(
+        number.as_f64(),
+        &picture.as_str(),
+        &format,
+        context.char_index,
+    )?;
+
+    Ok(Value::string(context.arena, &formatted))
+}
+
 pub fn fn_round<'a>(
     context: FunctionContext<'a, '_>,
     args: &[&'a Value<'a>],
@@ -1566,15 +2072,11 @@ pub fn fn_round<'a>(
     let num = num.round_ties_even();
     let num = multiply_by_pow10(num, -precision)?;
 
-    Ok(Value::number(context.arena, num))
-}
-
-fn is_array_of_strings(value: &Value) -> bool {
-    if let Value::Array(elements, _) = value {
-        elements.iter().all(|v| v.is_string())
-    } else {
-        false
-    }
+    Ok(Value::finite_number(
+        context.arena,
+        num,
+        context.char_index,
+    )?)
 }
 
 pub fn fn_reduce<'a>(
@@ -1584,7 +2086,7 @@ pub fn fn_reduce<'a>(
     max_args!(context, args, 3);
 
     if args.len() < 2 {
-        return Err(Error::T0410ArgumentNotValid(0, 2, context.name.to_string()));
+        return Err(Error::t0410(context.char_index, 2, context.name));
     }
 
     let original_value = args[0];
@@ -1617,41 +2119,36 @@ pub fn fn_reduce<'a>(
     }
 
     if !func.is_function() {
-        return Err(Error::T0410ArgumentNotValid(1, 1, context.name.to_string()));
+        return Err(Error::t0410(context.char_index, 1, context.name));
     }
 
-    let mut accumulator = init.unwrap_or_else(|| elements[0]);
-
-    let has_init_value = init.is_some();
-    let is_non_single_array_of_strings = is_array_of_strings(original_value) && elements.len() > 1;
-
-    let start_index = if has_init_value || is_non_single_array_of_strings {
-        0
-    } else {
-        1
+    // Without an initial value, the first item starts the accumulator
+    let (mut accumulator, start_index) = match init {
+        Some(init) if !init.is_undefined() => (init, 0),
+        _ => (elements[0], 1),
     };
 
-    for (index, value) in elements[start_index..].iter().enumerate() {
-        let index_value = Value::number(context.arena, index as f64);
+    // The index and the whole array are only passed if the function declares them
+    let arity = func.arity();
 
-        let result =
-            context.evaluate_function(func, &[accumulator, value, index_value, original_value]);
-
-        match result {
-            Ok(new_accumulator) => {
-                accumulator = new_accumulator;
-            }
-            Err(_) => {
-                return Err(Error::T0410ArgumentNotValid(1, 1, context.name.to_string()));
-            }
+    for (index, value) in elements.iter().enumerate().skip(start_index) {
+        let mut args = vec![accumulator, *value];
+        if arity >= 3 {
+            args.push(Value::number(context.arena, index as f64));
         }
+        if arity >= 4 {
+            args.push(original_value);
+        }
+
+        accumulator = context.evaluate_function(func, &args)?;
     }
 
     Ok(accumulator)
 }
 
-// We need to do this multiplication by powers of 10 in a string to avoid
-// floating point precision errors which will affect the rounding algorithm
+// We do this multiplication by powers of 10 in a string, shifting the decimal exponent, to
+// avoid floating point precision errors which would affect the rounding algorithm: 650000 * 1e-5
+// is 6.500000000000001, where this gives exactly 6.5.
 fn multiply_by_pow10(num: f64, pow: isize) -> Result<f64> {
     let num_str = format!("{}e{}", num, pow);
     num_str
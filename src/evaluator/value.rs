@@ -10,9 +10,10 @@ use hashbrown::HashMap;
 
 use super::frame::Frame;
 use super::functions::FunctionContext;
-use crate::parser::ast::{Ast, AstKind};
+use crate::parser::ast::{Ast, AstKind, RegexLiteral};
 use crate::{Error, Result};
 
+pub mod convert;
 pub mod impls;
 pub mod iterator;
 mod range;
@@ -20,7 +21,12 @@ pub mod serialize;
 
 use self::range::Range;
 use self::serialize::{DumpFormatter, PrettyFormatter, Serializer};
-pub use iterator::MemberIterator;
+pub use convert::{FromValue, IntoValue};
+pub use iterator::{DescendantIterator, MemberIterator, ValueIterator};
+
+/// The implementation of a native function, either built-in or registered by the host.
+pub type NativeFnImpl<'a> =
+    dyn Fn(FunctionContext<'a, '_>, &[&'a Value<'a>]) -> Result<&'a Value<'a>> + 'a;
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -60,13 +66,14 @@ pub enum Value<'a> {
     NativeFn {
         name: String,
         arity: usize,
-        func: fn(FunctionContext<'a, '_>, &[&'a Value<'a>]) -> Result<&'a Value<'a>>,
+        func: &'a NativeFnImpl<'a>,
     },
     Transformer {
         pattern: std::boxed::Box<Ast>,
         update: std::boxed::Box<Ast>,
         delete: Option<std::boxed::Box<Ast>>,
     },
+    Regex(RegexLiteral),
 }
 
 #[allow(clippy::mut_from_ref)]
@@ -77,7 +84,7 @@ impl<'a> Value<'a> {
         unsafe { std::mem::transmute::<&Value<'static>, &'a Value<'a>>(&UNDEFINED) }
     }
 
-    pub fn null(arena: &Bump) -> &mut Value {
+    pub fn null(arena: &Bump) -> &mut Value<'_> {
         arena.alloc(Value::Null)
     }
 
@@ -89,15 +96,30 @@ impl<'a> Value<'a> {
         }
     }
 
-    pub fn number(arena: &Bump, value: impl Into<f64>) -> &mut Value {
+    pub fn number(arena: &Bump, value: impl Into<f64>) -> &mut Value<'_> {
         arena.alloc(Value::Number(value.into()))
     }
 
+    /// Like [`Value::number`], but fails with D1001 at `char_index` if the value is NaN or
+    /// infinite, for coercions and functions whose result must be a number JSONata can represent.
+    pub fn finite_number(
+        arena: &Bump,
+        value: impl Into<f64>,
+        char_index: usize,
+    ) -> Result<&mut Value<'_>> {
+        let value = value.into();
+        if value.is_finite() {
+            Ok(Value::number(arena, value))
+        } else {
+            Err(Error::D1001NumberOfOutRange(char_index, value))
+        }
+    }
+
     pub fn string(arena: &'a Bump, value: &str) -> &'a mut Value<'a> {
         arena.alloc(Value::String(BumpString::from_str_in(value, arena)))
     }
 
-    pub fn array(arena: &Bump, flags: ArrayFlags) -> &mut Value {
+    pub fn array(arena: &Bump, flags: ArrayFlags) -> &mut Value<'_> {
         let v = BumpVec::new_in(arena);
         arena.alloc(Value::Array(v, flags))
     }
@@ -110,14 +132,24 @@ impl<'a> Value<'a> {
         arena.alloc(Value::Array(arr, flags))
     }
 
-    pub fn array_with_capacity(arena: &Bump, capacity: usize, flags: ArrayFlags) -> &mut Value {
+    /// Builds a plain (non-sequence) array from `items`, for constructing inputs on the host side.
+    pub fn array_of(
+        arena: &'a Bump,
+        items: impl IntoIterator<Item = &'a Value<'a>>,
+    ) -> &'a mut Value<'a> {
+        let mut arr = BumpVec::new_in(arena);
+        arr.extend(items);
+        Value::array_from(arena, arr, ArrayFlags::empty())
+    }
+
+    pub fn array_with_capacity(arena: &Bump, capacity: usize, flags: ArrayFlags) -> &mut Value<'_> {
         arena.alloc(Value::Array(
             BumpVec::with_capacity_in(capacity, arena),
             flags,
         ))
     }
 
-    pub fn object(arena: &Bump) -> &mut Value {
+    pub fn object(arena: &Bump) -> &mut Value<'_> {
         arena.alloc(Value::Object(HashMap::new_in(arena)))
     }
 
@@ -132,7 +164,20 @@ impl<'a> Value<'a> {
         result
     }
 
-    pub fn object_with_capacity(arena: &Bump, capacity: usize) -> &mut Value {
+    /// Builds an object from `entries`, for constructing inputs on the host side. Later entries
+    /// overwrite earlier ones with the same key.
+    pub fn object_of<K: AsRef<str>>(
+        arena: &'a Bump,
+        entries: impl IntoIterator<Item = (K, &'a Value<'a>)>,
+    ) -> &'a mut Value<'a> {
+        let result = Value::object(arena);
+        for (key, value) in entries {
+            result.insert(key.as_ref(), value);
+        }
+        result
+    }
+
+    pub fn object_with_capacity(arena: &Bump, capacity: usize) -> &mut Value<'_> {
         arena.alloc(Value::Object(HashMap::with_capacity_in(capacity, arena)))
     }
 
@@ -149,16 +194,17 @@ impl<'a> Value<'a> {
         })
     }
 
-    pub fn nativefn(
-        arena: &'a Bump,
-        name: &str,
-        arity: usize,
-        func: fn(FunctionContext<'a, '_>, &[&'a Value<'a>]) -> Result<&'a Value<'a>>,
-    ) -> &'a mut Value<'a> {
+    /// Creates a native function from either a plain function or a closure. Note that the
+    /// closure is moved into the arena, which doesn't run destructors, so anything it captures
+    /// won't be dropped.
+    pub fn nativefn<F>(arena: &'a Bump, name: &str, arity: usize, func: F) -> &'a mut Value<'a>
+    where
+        F: Fn(FunctionContext<'a, '_>, &[&'a Value<'a>]) -> Result<&'a Value<'a>> + 'a,
+    {
         arena.alloc(Value::NativeFn {
             name: name.to_string(),
             arity,
-            func,
+            func: arena.alloc(func),
         })
     }
 
@@ -175,6 +221,10 @@ impl<'a> Value<'a> {
         })
     }
 
+    pub fn regex(arena: &'a Bump, regex: &RegexLiteral) -> &'a mut Value<'a> {
+        arena.alloc(Value::Regex(regex.clone()))
+    }
+
     pub fn range(arena: &'a Bump, start: isize, end: isize) -> &'a mut Value<'a> {
         arena.alloc(Value::Range(Range::new(arena, start, end)))
     }
@@ -192,11 +242,11 @@ impl<'a> Value<'a> {
     }
 
     pub fn is_bool(&self) -> bool {
-        matches!(&self, Value::Bool(..))
+        matches!(*self, Value::Bool(..))
     }
 
     pub fn is_number(&self) -> bool {
-        matches!(&self, Value::Number(..))
+        matches!(*self, Value::Number(..))
     }
 
     pub fn is_integer(&self) -> bool {
@@ -214,11 +264,11 @@ impl<'a> Value<'a> {
         }
     }
 
-    pub fn is_array_of_valid_numbers(&self) -> Result<bool> {
+    pub fn is_array_of_valid_numbers(&self, char_index: usize) -> Result<bool> {
         match self {
             Value::Array(ref a, _) => {
                 for member in a.iter() {
-                    if !member.is_valid_number()? {
+                    if !member.is_valid_number(char_index)? {
                         return Ok(false);
                     }
                 }
@@ -242,13 +292,15 @@ impl<'a> Value<'a> {
         }
     }
 
-    pub fn is_valid_number(&self) -> Result<bool> {
+    /// Whether this is a number other than NaN. An infinite number is an error (D1001), which is
+    /// reported at `char_index`.
+    pub fn is_valid_number(&self, char_index: usize) -> Result<bool> {
         match self {
             Value::Number(n) => {
                 if n.is_nan() {
                     Ok(false)
                 } else if n.is_infinite() {
-                    Err(Error::D1001NumberOfOutRange(*n))
+                    Err(Error::D1001NumberOfOutRange(char_index, *n))
                 } else {
                     Ok(true)
                 }
@@ -280,13 +332,24 @@ impl<'a> Value<'a> {
         matches!(*self, Value::Object(..))
     }
 
+    /// Returns true for anything that can be invoked: lambdas (including partially applied
+    /// functions), native functions, transformers and regexes.
     pub fn is_function(&self) -> bool {
         matches!(
             *self,
-            Value::Lambda { .. } | Value::NativeFn { .. } | Value::Transformer { .. }
+            Value::Lambda { .. }
+                | Value::NativeFn { .. }
+                | Value::Transformer { .. }
+                | Value::Regex(..)
         )
     }
 
+    pub fn is_regex(&self) -> bool {
+        matches!(*self, Value::Regex(..))
+    }
+
+    /// Casts to a boolean following the rules of `$boolean`, which conditions and predicates also
+    /// use. Note that functions are always false.
     pub fn is_truthy(&'a self) -> bool {
         match *self {
             Value::Undefined => false,
@@ -307,8 +370,12 @@ impl<'a> Value<'a> {
                 }
             },
             Value::Object(ref o) => !o.is_empty(),
-            Value::Lambda { .. } | Value::NativeFn { .. } | Value::Transformer { .. } => false,
-            Value::Range(ref r) => !r.is_empty(),
+            Value::Lambda { .. }
+            | Value::NativeFn { .. }
+            | Value::Transformer { .. }
+            | Value::Regex(..) => false,
+            // A range is truthy if any of its numbers is, as for an array
+            Value::Range(..) => self.members().any(|member| member.is_truthy()),
         }
     }
 
@@ -329,13 +396,25 @@ impl<'a> Value<'a> {
         }
     }
 
-    pub fn entries(&self) -> hashbrown::hash_map::Iter<'_, BumpString<'a>, &'a Value> {
+    /// Iterates over the members of an array, or over a single value as if it were an array of
+    /// one. Unlike [`Value::members`], this never panics, and undefined yields nothing.
+    pub fn iter(&'a self) -> ValueIterator<'a> {
+        ValueIterator::new(self)
+    }
+
+    pub fn descendants(&'a self) -> DescendantIterator<'a> {
+        DescendantIterator::new(self)
+    }
+
+    pub fn entries(&self) -> hashbrown::hash_map::Iter<'_, BumpString<'a>, &'a Value<'_>> {
         match self {
             Value::Object(map) => map.iter(),
             _ => panic!("Not an object"),
         }
     }
 
+    /// The number of parameters a function declares. For a partially applied function, this is
+    /// the number of `?` placeholders left to fill. Panics if this isn't a function.
     pub fn arity(&self) -> usize {
         match *self {
             Value::Lambda { ref ast, .. } => {
@@ -346,7 +425,7 @@ impl<'a> Value<'a> {
                 }
             }
             Value::NativeFn { arity, .. } => arity,
-            Value::Transformer { .. } => 1,
+            Value::Transformer { .. } | Value::Regex(..) => 1,
             _ => panic!("Not a function"),
         }
     }
@@ -388,19 +467,88 @@ impl<'a> Value<'a> {
         }
     }
 
+    /// Returns the members if this is an array, or `None` for any other kind of value,
+    /// including undefined. Unlike [`Value::members`], this never panics.
+    pub fn as_array(&self) -> Option<&[&'a Value<'a>]> {
+        match *self {
+            Value::Array(ref array, _) => Some(array),
+            _ => None,
+        }
+    }
+
+    /// Returns the entries if this is an object, or `None` for any other kind of value,
+    /// including undefined. Unlike [`Value::entries`], this never panics.
+    pub fn as_object(
+        &self,
+    ) -> Option<&HashMap<BumpString<'a>, &'a Value<'a>, DefaultHashBuilder, &'a Bump>> {
+        match *self {
+            Value::Object(ref map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if this is a number, or `None` for any other kind of value,
+    /// including undefined. Unlike [`Value::as_f64`], this never panics.
+    pub fn try_as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the string if this is a string, or `None` for any other kind of value,
+    /// including undefined. Unlike [`Value::as_str`], this never panics.
+    pub fn try_as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// The number of members of an array or range, or of keys of an object.
+    ///
+    /// Panics for any other kind of value.
     pub fn len(&self) -> usize {
         match *self {
             Value::Array(ref array, _) => array.len(),
             Value::Range(ref range) => range.len(),
-            _ => panic!("Not an array"),
+            Value::Object(ref object) => object.len(),
+            _ => panic!("Not an array or object"),
         }
     }
 
+    /// Whether an array, range or object has no members.
+    ///
+    /// Panics for any other kind of value.
     pub fn is_empty(&self) -> bool {
         match *self {
             Value::Array(ref array, _) => array.is_empty(),
             Value::Range(ref range) => range.is_empty(),
-            _ => panic!("Not an array"),
+            Value::Object(ref object) => object.is_empty(),
+            _ => panic!("Not an array or object"),
+        }
+    }
+
+    /// Looks up `key` if this is an object, returning undefined for a missing key or any
+    /// other kind of value. Unlike [`Value::get_entry`], this never panics.
+    ///
+    /// Calls can be chained to navigate a result, e.g. `result.get("a").index(0).get("b")`.
+    pub fn get(&self, key: &str) -> &'a Value<'a> {
+        match *self {
+            Value::Object(..) => self.get_entry(key),
+            _ => Value::undefined(),
+        }
+    }
+
+    /// Returns the member at `index` if this is an array, or undefined if it's out of bounds
+    /// or not an array. Unlike [`Value::get_member`], this never panics.
+    ///
+    /// This mirrors `Index<usize>`, but the returned reference lives as long as the arena.
+    #[allow(clippy::should_implement_trait)]
+    pub fn index(&self, index: usize) -> &'a Value<'a> {
+        match *self {
+            Value::Array(..) | Value::Range(..) => self.get_member(index),
+            _ => Value::undefined(),
         }
     }
 
@@ -437,6 +585,25 @@ impl<'a> Value<'a> {
         }
     }
 
+    /// Shallow-merges the entries of the `updates` object into this one, replacing any fields
+    /// that are already present. Nested objects are shared with `updates` rather than merged.
+    ///
+    /// Panics unless both values are objects.
+    pub(crate) fn merge_into(&mut self, updates: &Value<'a>) {
+        let Value::Object(ref entries) = *updates else {
+            panic!("Not an object")
+        };
+        match *self {
+            Value::Object(ref mut map) => {
+                map.reserve(entries.len());
+                for (key, value) in entries.iter() {
+                    map.insert(BumpString::from_str_in(key, map.allocator()), *value);
+                }
+            }
+            _ => panic!("Not an object"),
+        }
+    }
+
     pub fn remove(&mut self, key: &str) {
         match *self {
             Value::Object(ref mut map) => map.remove(key),
@@ -497,6 +664,11 @@ impl<'a> Value<'a> {
         }
     }
 
+    /// Copies the value into a new allocation that can be modified without affecting this one.
+    ///
+    /// The copy is shallow: an array or object gets its own list of members or entries, but the
+    /// members themselves are shared references into the arena, which are never modified in
+    /// place. So cloning costs the length of the top level rather than the size of the document.
     pub fn clone(&'a self, arena: &'a Bump) -> &'a mut Value<'a> {
         match self {
             Self::Undefined => arena.alloc(Value::Undefined),
@@ -507,12 +679,17 @@ impl<'a> Value<'a> {
             Self::Array(a, f) => Value::array_from(arena, a.clone(), *f),
             Self::Object(o) => Value::object_from(o, arena),
             Self::Lambda { ast, input, frame } => Value::lambda(arena, ast, input, frame.clone()),
-            Self::NativeFn { name, arity, func } => Value::nativefn(arena, name, *arity, *func),
+            Self::NativeFn { name, arity, func } => arena.alloc(Value::NativeFn {
+                name: name.clone(),
+                arity: *arity,
+                func: *func,
+            }),
             Self::Transformer {
                 pattern,
                 update,
                 delete,
             } => Value::transformer(arena, pattern, update, delete),
+            Self::Regex(regex) => Value::regex(arena, regex),
             Self::Range(range) => Value::range_from(arena, range),
         }
     }
@@ -526,10 +703,10 @@ impl<'a> Value<'a> {
 
     pub fn serialize(&'a self, pretty: bool) -> String {
         if pretty {
-            let serializer = Serializer::new(PrettyFormatter::default(), false);
+            let serializer = Serializer::new(PrettyFormatter::default(), None);
             serializer.serialize(self).expect("Shouldn't fail")
         } else {
-            let serializer = Serializer::new(DumpFormatter, false);
+            let serializer = Serializer::new(DumpFormatter, None);
             serializer.serialize(self).expect("Shouldn't fail")
         }
     }
@@ -551,3 +728,172 @@ impl<'a> Value<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clone_shares_members_and_isolates_changes() {
+        let arena = Bump::new();
+        let one: &Value = Value::number(&arena, 1);
+        let two: &Value = Value::number(&arena, 2);
+        let inner: &Value = Value::array_of(&arena, [one]);
+        let array: &Value = Value::array_of(&arena, [inner, two]);
+        let object: &Value = Value::object_of(&arena, [("inner", inner)]);
+
+        let cloned_array = array.clone(&arena);
+        cloned_array.push(Value::number(&arena, 3));
+        assert_eq!(array.len(), 2);
+        assert_eq!(cloned_array.len(), 3);
+        assert!(std::ptr::eq(
+            array.get_member(0),
+            cloned_array.get_member(0)
+        ));
+
+        let cloned_object = object.clone(&arena);
+        cloned_object.insert("added", Value::bool(true));
+        cloned_object.remove("inner");
+        assert!(object.get("added").is_undefined());
+        assert!(std::ptr::eq(object.get("inner"), inner));
+    }
+
+    #[test]
+    fn merge_into_adds_and_overwrites_fields() {
+        let arena = Bump::new();
+        let one: &Value = Value::number(&arena, 1);
+        let two: &Value = Value::number(&arena, 2);
+        let nested: &Value = Value::object_of(&arena, [("x", one)]);
+        let object = Value::object_of(&arena, [("a", one), ("b", one)]);
+        let updates: &Value = Value::object_of(&arena, [("b", two), ("c", nested)]);
+
+        object.merge_into(updates);
+        assert_eq!(object.len(), 3);
+        assert_eq!(object.get("a").as_f64(), 1.0);
+        assert_eq!(object.get("b").as_f64(), 2.0);
+        assert!(std::ptr::eq(object.get("c"), nested));
+
+        // The updates themselves are left alone
+        assert_eq!(updates.len(), 2);
+        assert!(updates.get("a").is_undefined());
+
+        object.merge_into(Value::object(&arena));
+        assert_eq!(object.len(), 3);
+    }
+
+    #[test]
+    fn shape_predicates() {
+        let arena = Bump::new();
+        let empty_array: &Value = Value::array(&arena, ArrayFlags::empty());
+        let empty_object: &Value = Value::object(&arena);
+        let values: [(&str, &Value, [bool; 8]); 10] = [
+            (
+                "undefined",
+                Value::undefined(),
+                [true, false, false, false, false, false, false, false],
+            ),
+            (
+                "null",
+                Value::null(&arena),
+                [false, true, false, false, false, false, false, false],
+            ),
+            (
+                "bool",
+                Value::bool(false),
+                [false, false, true, false, false, false, false, false],
+            ),
+            (
+                "number",
+                Value::number(&arena, 0),
+                [false, false, false, true, false, false, false, false],
+            ),
+            (
+                "string",
+                Value::string(&arena, ""),
+                [false, false, false, false, true, false, false, false],
+            ),
+            (
+                "array",
+                empty_array,
+                [false, false, false, false, false, true, false, false],
+            ),
+            (
+                "range",
+                Value::range(&arena, 1, 3),
+                [false, false, false, false, false, true, false, false],
+            ),
+            (
+                "object",
+                empty_object,
+                [false, false, false, false, false, false, true, false],
+            ),
+            (
+                "function",
+                Value::nativefn(&arena, "f", 0, |_, _| Ok(Value::undefined())),
+                [false, false, false, false, false, false, false, true],
+            ),
+            (
+                "nested",
+                Value::array_of(&arena, [empty_object]),
+                [false, false, false, false, false, true, false, false],
+            ),
+        ];
+
+        for (kind, value, expected) in values {
+            let actual = [
+                value.is_undefined(),
+                value.is_null(),
+                value.is_bool(),
+                value.is_number(),
+                value.is_string(),
+                value.is_array(),
+                value.is_object(),
+                value.is_function(),
+            ];
+            assert_eq!(actual, expected, "{}", kind);
+        }
+
+        assert!(empty_array.is_empty());
+        assert_eq!(Value::range(&arena, 1, 3).len(), 3);
+        assert!(empty_object.is_empty());
+        let object: &Value = Value::object_of(&arena, [("a", Value::bool(true))]);
+        assert_eq!(object.len(), 1);
+        assert!(!object.is_empty());
+    }
+
+    #[test]
+    fn sequences_are_truthy_like_arrays() {
+        let arena = Bump::new();
+        let zero: &Value = Value::number(&arena, 0);
+        let one: &Value = Value::number(&arena, 1);
+
+        for flags in [ArrayFlags::empty(), ArrayFlags::SEQUENCE] {
+            let empty: &Value = Value::array(&arena, flags);
+            let zeros = Value::array_with_capacity(&arena, 2, flags);
+            zeros.push(zero);
+            zeros.push(zero);
+            let mixed = Value::array_with_capacity(&arena, 2, flags);
+            mixed.push(zero);
+            mixed.push(one);
+
+            assert!(!empty.is_truthy(), "{:?}", flags);
+            assert!(!zeros.is_truthy(), "{:?}", flags);
+            assert!(mixed.is_truthy(), "{:?}", flags);
+        }
+    }
+
+    #[test]
+    fn finite_number() {
+        let arena = Bump::new();
+        assert_eq!(
+            *Value::finite_number(&arena, 1.5, 0).unwrap(),
+            Value::Number(1.5)
+        );
+        for n in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            assert_eq!(
+                Value::finite_number(&arena, n, 0).unwrap_err().code(),
+                "D1001"
+            );
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+
+use bumpalo::Bump;
+
+use super::{ArrayFlags, Value};
+
+/// Conversion of Rust values into arena-allocated [`Value`]s.
+///
+/// Values live in a [`Bump`] arena, so this takes the arena rather than being a plain `Into`.
+/// Arrays built from a `Vec` are plain (non-sequence) arrays, and `None` becomes undefined,
+/// which is dropped when it appears inside an array as JSONata arrays never hold undefined.
+pub trait IntoValue<'a> {
+    fn into_value(self, arena: &'a Bump) -> &'a Value<'a>;
+}
+
+impl<'a> IntoValue<'a> for &'a Value<'a> {
+    fn into_value(self, _arena: &'a Bump) -> &'a Value<'a> {
+        self
+    }
+}
+
+impl<'a> IntoValue<'a> for &str {
+    fn into_value(self, arena: &'a Bump) -> &'a Value<'a> {
+        Value::string(arena, self)
+    }
+}
+
+impl<'a> IntoValue<'a> for String {
+    fn into_value(self, arena: &'a Bump) -> &'a Value<'a> {
+        Value::string(arena, &self)
+    }
+}
+
+impl<'a> IntoValue<'a> for f64 {
+    fn into_value(self, arena: &'a Bump) -> &'a Value<'a> {
+        Value::number(arena, self)
+    }
+}
+
+impl<'a> IntoValue<'a> for i64 {
+    fn into_value(self, arena: &'a Bump) -> &'a Value<'a> {
+        Value::number(arena, self as f64)
+    }
+}
+
+impl<'a> IntoValue<'a> for bool {
+    fn into_value(self, _arena: &'a Bump) -> &'a Value<'a> {
+        Value::bool(self)
+    }
+}
+
+impl<'a, T: IntoValue<'a>> IntoValue<'a> for Option<T> {
+    fn into_value(self, arena: &'a Bump) -> &'a Value<'a> {
+        match self {
+            Some(value) => value.into_value(arena),
+            None => Value::undefined(),
+        }
+    }
+}
+
+impl<'a, T: IntoValue<'a>> IntoValue<'a> for Vec<T> {
+    fn into_value(self, arena: &'a Bump) -> &'a Value<'a> {
+        let result = Value::array_with_capacity(arena, self.len(), ArrayFlags::empty());
+        for item in self {
+            let item = item.into_value(arena);
+            if !item.is_undefined() {
+                result.push(item);
+            }
+        }
+        result
+    }
+}
+
+impl<'a, K: AsRef<str>, T: IntoValue<'a>, S> IntoValue<'a> for HashMap<K, T, S> {
+    fn into_value(self, arena: &'a Bump) -> &'a Value<'a> {
+        let result = Value::object_with_capacity(arena, self.len());
+        for (key, value) in self {
+            let value = value.into_value(arena);
+            if !value.is_undefined() {
+                result.insert(key.as_ref(), value);
+            }
+        }
+        result
+    }
+}
+
+/// Conversion of [`Value`]s back into Rust values, the counterpart of [`IntoValue`].
+///
+/// Returns `None` if the value has the wrong type. Since JSONata collapses a sequence of one
+/// value to the value itself, and an empty one to undefined, a `Vec` is converted from any value,
+/// with undefined giving an empty `Vec` and any other non-array a `Vec` of one. `Option` is
+/// converted to `None` from undefined or null.
+pub trait FromValue<'a>: Sized {
+    fn from_value(value: &'a Value<'a>) -> Option<Self>;
+}
+
+impl<'a> FromValue<'a> for &'a Value<'a> {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        Some(value)
+    }
+}
+
+impl<'a> FromValue<'a> for &'a str {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        value.try_as_str()
+    }
+}
+
+impl<'a> FromValue<'a> for String {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        value.try_as_str().map(String::from)
+    }
+}
+
+impl<'a> FromValue<'a> for f64 {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        value.try_as_f64()
+    }
+}
+
+impl<'a> FromValue<'a> for i64 {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        if value.is_integer() {
+            Some(value.as_f64() as i64)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for bool {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        match *value {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, T: FromValue<'a>> FromValue<'a> for Option<T> {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        if value.is_undefined() || value.is_null() {
+            Some(None)
+        } else {
+            T::from_value(value).map(Some)
+        }
+    }
+}
+
+impl<'a, T: FromValue<'a>> FromValue<'a> for Vec<T> {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        value.iter().map(T::from_value).collect()
+    }
+}
+
+impl<'a, K, T, S> FromValue<'a> for HashMap<K, T, S>
+where
+    K: From<&'a str> + Eq + Hash,
+    T: FromValue<'a>,
+    S: BuildHasher + Default,
+{
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        value
+            .as_object()?
+            .iter()
+            .map(|(key, value)| Some((K::from(key.as_str()), T::from_value(value)?)))
+            .collect()
+    }
+}
+
+/// Converts any value but undefined, as `$string` would serialize it: functions become empty
+/// strings, numbers that aren't finite become null, and undefined members of objects are left
+/// out. Integers within the range a double represents exactly are kept as JSON integers.
+impl<'a> FromValue<'a> for serde_json::Value {
+    fn from_value(value: &'a Value<'a>) -> Option<Self> {
+        const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+        Some(match *value {
+            Value::Undefined => return None,
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(b),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER => {
+                serde_json::Value::from(n as i64)
+            }
+            Value::Number(n) => serde_json::Number::from_f64(n)
+                .map_or(serde_json::Value::Null, serde_json::Value::Number),
+            Value::String(ref s) => serde_json::Value::String(s.to_string()),
+            Value::Array(..) | Value::Range(..) => {
+                serde_json::Value::Array(value.members().filter_map(Self::from_value).collect())
+            }
+            Value::Object(ref map) => serde_json::Value::Object(
+                map.iter()
+                    .filter_map(|(key, value)| Some((key.to_string(), Self::from_value(value)?)))
+                    .collect(),
+            ),
+            Value::Lambda { .. }
+            | Value::NativeFn { .. }
+            | Value::Transformer { .. }
+            | Value::Regex(..) => serde_json::Value::String(String::new()),
+        })
+    }
+}
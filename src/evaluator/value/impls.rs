@@ -63,10 +63,7 @@ impl<'a> Index<&str> for Value<'a> {
     type Output = Value<'a>;
 
     fn index(&self, index: &str) -> &Self::Output {
-        match *self {
-            Value::Object(..) => self.get_entry(index),
-            _ => Value::undefined(),
-        }
+        self.get(index)
     }
 }
 
@@ -74,10 +71,7 @@ impl<'a> Index<usize> for Value<'a> {
     type Output = Value<'a>;
 
     fn index(&self, index: usize) -> &Self::Output {
-        match *self {
-            Value::Array(..) | Value::Range(..) => self.get_member(index),
-            _ => Value::undefined(),
-        }
+        Value::index(self, index)
     }
 }
 
@@ -94,6 +88,7 @@ impl std::fmt::Debug for Value<'_> {
             Self::Lambda { .. } => write!(f, "<lambda>"),
             Self::NativeFn { .. } => write!(f, "<nativefn>"),
             Self::Transformer { .. } => write!(f, "<transformer>"),
+            Self::Regex(r) => write!(f, "/{}/", r.as_str()),
             Self::Range(r) => write!(f, "<range({},{})>", r.start(), r.end()),
         }
     }
@@ -127,6 +122,7 @@ impl Hash for Value<'_> {
             Value::Lambda { .. } => generate_random_hash(state),
             Value::NativeFn { name, .. } => name.hash(state),
             Value::Transformer { .. } => generate_random_hash(state),
+            Value::Regex(r) => r.as_str().hash(state),
         }
     }
 }
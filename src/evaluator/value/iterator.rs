@@ -60,11 +60,83 @@ impl<'a> DoubleEndedIterator for MemberIterator<'a> {
     }
 }
 
+/// Yields the members of an array, or a single value once as if it were an array of one.
+/// Undefined is treated as an empty sequence.
+pub enum ValueIterator<'a> {
+    Members(MemberIterator<'a>),
+    Single(Option<&'a Value<'a>>),
+}
+
+impl<'a> ValueIterator<'a> {
+    pub fn new(value: &'a Value<'a>) -> Self {
+        match value {
+            Value::Array(..) | Value::Range(..) => Self::Members(MemberIterator::new(value)),
+            Value::Undefined => Self::Single(None),
+            _ => Self::Single(Some(value)),
+        }
+    }
+}
+
+impl<'a> Iterator for ValueIterator<'a> {
+    type Item = &'a Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Members(members) => members.next(),
+            Self::Single(value) => value.take(),
+        }
+    }
+}
+
+/// Walks a value depth-first, yielding the value itself followed by everything nested
+/// within it. Arrays are traversed but never yielded themselves, which is what the `**`
+/// operator requires.
+///
+/// This is the walk behind `**` only. `$keys` looks no deeper than one array of objects, and a
+/// transform copies each container after its members, so neither is a flat walk like this one.
+pub struct DescendantIterator<'a> {
+    stack: Vec<&'a Value<'a>>,
+}
+
+impl<'a> DescendantIterator<'a> {
+    pub fn new(value: &'a Value<'a>) -> Self {
+        Self { stack: vec![value] }
+    }
+}
+
+impl<'a> Iterator for DescendantIterator<'a> {
+    type Item = &'a Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let value = self.stack.pop()?;
+
+            // Children are pushed in reverse so that they're popped in their natural order
+            match value {
+                Value::Array(..) | Value::Range(..) => {
+                    self.stack.extend(value.members().rev());
+                    continue;
+                }
+                Value::Object(map) => {
+                    let start = self.stack.len();
+                    self.stack.extend(map.values().copied());
+                    self.stack[start..].reverse();
+                }
+                _ => (),
+            }
+
+            return Some(value);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use bumpalo::Bump;
 
+    use crate::evaluator::value::ArrayFlags;
+
     #[test]
     fn forward() {
         let arena = Bump::new();
@@ -103,4 +175,72 @@ mod tests {
         assert_eq!(*iter.next().unwrap(), 1_isize);
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn descendants() {
+        let arena = Bump::new();
+
+        // {"a": [1, [2, {"b": 3}], 4]}
+        let inner = Value::object(&arena);
+        inner.insert("b", Value::number(&arena, 3));
+        let nested = Value::array(&arena, ArrayFlags::empty());
+        nested.push(Value::number(&arena, 2));
+        nested.push(inner);
+        let array = Value::array(&arena, ArrayFlags::empty());
+        array.push(Value::number(&arena, 1));
+        array.push(nested);
+        array.push(Value::number(&arena, 4));
+        let outer = Value::object(&arena);
+        outer.insert("a", array);
+
+        let mut iter = DescendantIterator::new(outer);
+        assert!(std::ptr::eq(iter.next().unwrap(), &*outer));
+        assert_eq!(*iter.next().unwrap(), 1_isize);
+        assert_eq!(*iter.next().unwrap(), 2_isize);
+        assert!(iter.next().unwrap().is_object());
+        assert_eq!(*iter.next().unwrap(), 3_isize);
+        assert_eq!(*iter.next().unwrap(), 4_isize);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn descendants_of_scalar() {
+        let arena = Bump::new();
+        let value = Value::number(&arena, 1);
+        let mut iter = DescendantIterator::new(value);
+        assert_eq!(*iter.next().unwrap(), 1_isize);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn iter_scalar() {
+        let arena = Bump::new();
+        let value = Value::number(&arena, 1);
+        let items = value.iter().collect::<Vec<_>>();
+        assert_eq!(items.len(), 1);
+        assert!(std::ptr::eq(items[0], value));
+
+        assert_eq!(Value::undefined().iter().count(), 0);
+    }
+
+    #[test]
+    fn iter_array() {
+        let arena = Bump::new();
+        let array = Value::array_of(
+            &arena,
+            [
+                &*Value::number(&arena, 1),
+                Value::string(&arena, "a"),
+                Value::null(&arena),
+            ],
+        );
+        let items = array.iter().collect::<Vec<_>>();
+        assert_eq!(items.len(), 3);
+        assert_eq!(*items[0], 1_isize);
+        assert!(items[1].is_string());
+        assert!(items[2].is_null());
+
+        assert_eq!(Value::range(&arena, 1, 5).iter().count(), 5);
+        assert_eq!(Value::array(&arena, ArrayFlags::empty()).iter().count(), 0);
+    }
 }
@@ -103,11 +103,12 @@ impl Formatter for PrettyFormatter {
 pub struct Serializer<T: Formatter> {
     output: Vec<u8>,
     formatter: T,
-    fail_on_invalid_numbers: bool,
+    /// Where to report an infinite number as an error, or `None` to write it out regardless
+    fail_on_invalid_numbers: Option<usize>,
 }
 
 impl<T: Formatter> Serializer<T> {
-    pub fn new(formatter: T, fail_on_invalid_numbers: bool) -> Self {
+    pub fn new(formatter: T, fail_on_invalid_numbers: Option<usize>) -> Self {
         Serializer {
             output: Vec::with_capacity(1024),
             formatter,
@@ -279,8 +280,8 @@ impl<T: Formatter> Serializer<T> {
             Value::Null => self.write(b"null"),
             Value::String(ref string) => self.write_string(string),
             Value::Number(n) => {
-                if self.fail_on_invalid_numbers {
-                    value.is_valid_number()?;
+                if let Some(char_index) = self.fail_on_invalid_numbers {
+                    value.is_valid_number(char_index)?;
                 }
                 self.write_number(*n);
             }
@@ -288,9 +289,10 @@ impl<T: Formatter> Serializer<T> {
             Value::Bool(false) => self.write(b"false"),
             Value::Array(..) | Value::Range(..) => self.write_array(value)?,
             Value::Object(..) => self.write_object(value)?,
-            Value::Lambda { .. } | Value::NativeFn { .. } | Value::Transformer { .. } => {
-                self.write(b"\"\"")
-            }
+            Value::Lambda { .. }
+            | Value::NativeFn { .. }
+            | Value::Transformer { .. }
+            | Value::Regex(..) => self.write(b"\"\""),
         };
 
         Ok(())
@@ -6,14 +6,16 @@ use bumpalo::Bump;
 mod datetime;
 mod errors;
 mod evaluator;
+mod number_format;
 mod parser;
 
-pub use errors::Error;
+pub use errors::{Error, ErrorKind};
 pub use evaluator::functions::FunctionContext;
-pub use evaluator::value::{ArrayFlags, Value};
+pub use evaluator::value::{ArrayFlags, FromValue, IntoValue, Value};
+pub use parser::ast::{Ast, AstKind, BinaryOp, UnaryOp};
+pub use parser::{format_expression, parse};
 
 use evaluator::{frame::Frame, functions::*, Evaluator};
-use parser::ast::Ast;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -21,6 +23,9 @@ pub struct JsonAta<'a> {
     ast: Ast,
     frame: Frame<'a>,
     arena: &'a Bump,
+    undefined_as_null: bool,
+    lenient_comparisons: bool,
+    environment_function: bool,
 }
 
 impl<'a> JsonAta<'a> {
@@ -29,23 +34,52 @@ impl<'a> JsonAta<'a> {
             ast: parser::parse(expr)?,
             frame: Frame::new(),
             arena,
+            undefined_as_null: false,
+            lenient_comparisons: false,
+            environment_function: false,
         })
     }
 
+    /// Checks that an expression is syntactically valid without evaluating it, returning the
+    /// first syntax error if not. Nothing is allocated in an arena, so this is cheap enough to
+    /// run on every keystroke in an editor.
+    pub fn validate(expr: &str) -> Result<()> {
+        parser::parse(expr).map(|_| ())
+    }
+
+    /// The parsed and processed expression, for tools that want to inspect it without parsing
+    /// it again.
     pub fn ast(&self) -> &Ast {
         &self.ast
     }
 
+    /// When enabled, an undefined (missing) result is returned as `null` rather than undefined,
+    /// so that it serializes to JSON `null` instead of nothing. Off by default, which matches
+    /// JSONata.
+    pub fn set_undefined_as_null(&mut self, undefined_as_null: bool) {
+        self.undefined_as_null = undefined_as_null;
+    }
+
+    /// When enabled, comparing values that can't be ordered, e.g. `null < 1` or `1 < "2"`, gives
+    /// undefined rather than a T2010 or T2009 error. Off by default, which matches JSONata.
+    pub fn set_lenient_comparisons(&mut self, lenient_comparisons: bool) {
+        self.lenient_comparisons = lenient_comparisons;
+    }
+
+    /// When enabled, binds the non-standard `$environment()` function, which returns an object of
+    /// the variables visible where it's called, for debugging complex expressions. Off by default.
+    pub fn set_environment_function(&mut self, environment_function: bool) {
+        self.environment_function = environment_function;
+    }
+
     pub fn assign_var(&self, name: &str, value: &'a Value<'a>) {
         self.frame.bind(name, value)
     }
 
-    pub fn register_function(
-        &self,
-        name: &str,
-        arity: usize,
-        implementation: fn(FunctionContext<'a, '_>, &[&'a Value<'a>]) -> Result<&'a Value<'a>>,
-    ) {
+    pub fn register_function<F>(&self, name: &str, arity: usize, implementation: F)
+    where
+        F: Fn(FunctionContext<'a, '_>, &[&'a Value<'a>]) -> Result<&'a Value<'a>> + 'a,
+    {
         self.frame.bind(
             name,
             Value::nativefn(self.arena, name, arity, implementation),
@@ -53,7 +87,7 @@ impl<'a> JsonAta<'a> {
     }
 
     fn json_value_to_value(&self, json_value: &serde_json::Value) -> &'a mut Value<'a> {
-        return match json_value {
+        match json_value {
             serde_json::Value::Null => Value::null(self.arena),
             serde_json::Value::Bool(b) => self.arena.alloc(Value::Bool(*b)),
             serde_json::Value::Number(n) => Value::number(self.arena, n.as_f64().unwrap()),
@@ -65,16 +99,16 @@ impl<'a> JsonAta<'a> {
                     array.push(self.json_value_to_value(v))
                 }
 
-                return array;
+                array
             }
             serde_json::Value::Object(o) => {
                 let object = Value::object_with_capacity(self.arena, o.len());
                 for (k, v) in o.iter() {
                     object.insert(k, self.json_value_to_value(v));
                 }
-                return object;
+                object
             }
-        };
+        }
     }
 
     pub fn evaluate(
@@ -107,6 +141,23 @@ impl<'a> JsonAta<'a> {
             None => Value::undefined(),
         };
 
+        self.evaluate_input(input, max_depth, time_limit)
+    }
+
+    /// Evaluates the expression against an input that has already been built as a [`Value`],
+    /// e.g. with [`Value::object_of`] and [`Value::array_of`].
+    ///
+    /// The input is evaluated in place, so there is no JSON round trip or copy of the document.
+    pub fn evaluate_value(&self, input: &'a Value<'a>) -> Result<&'a Value<'a>> {
+        self.evaluate_input(input, None, None)
+    }
+
+    fn evaluate_input(
+        &self,
+        input: &'a Value<'a>,
+        max_depth: Option<usize>,
+        time_limit: Option<usize>,
+    ) -> Result<&'a Value<'a>> {
         // If the input is an array, wrap it in an array so that it gets treated as a single input
         let input = if input.is_array() {
             Value::wrap_in_array(self.arena, input, ArrayFlags::WRAPPED)
@@ -133,22 +184,30 @@ impl<'a> JsonAta<'a> {
         bind_native!("count", 1, fn_count);
         bind_native!("distinct", 1, fn_distinct);
         bind_native!("each", 2, fn_each);
-        bind_native!("error", 1, fn_error);
+        bind_native!("error", 3, fn_error);
+        bind_native!("eval", 2, fn_eval);
         bind_native!("exists", 1, fn_exists);
         bind_native!("fromMillis", 3, from_millis);
         bind_native!("toMillis", 2, to_millis);
         bind_native!("single", 2, single);
         bind_native!("filter", 2, fn_filter);
+        bind_native!("first", 1, fn_first);
         bind_native!("floor", 1, fn_floor);
+        bind_native!("formatNumber", 3, fn_format_number);
         bind_native!("join", 2, fn_join);
         bind_native!("keys", 1, fn_keys);
+        bind_native!("last", 1, fn_last);
         bind_native!("length", 1, fn_length);
         bind_native!("lookup", 2, fn_lookup);
         bind_native!("lowercase", 1, fn_lowercase);
         bind_native!("map", 2, fn_map);
+        bind_native!("match", 3, fn_match);
         bind_native!("max", 1, fn_max);
+        bind_native!("maxString", 1, fn_max_string);
         bind_native!("merge", 1, fn_merge);
+        bind_native!("mergeDeep", 1, fn_merge_deep);
         bind_native!("min", 1, fn_min);
+        bind_native!("minString", 1, fn_min_string);
         bind_native!("not", 1, fn_not);
         bind_native!("now", 2, fn_now);
         bind_native!("number", 1, fn_number);
@@ -164,18 +223,32 @@ impl<'a> JsonAta<'a> {
         bind_native!("sqrt", 1, fn_sqrt);
         bind_native!("string", 1, fn_string);
         bind_native!("substring", 3, fn_substring);
+        bind_native!("substringGraphemes", 3, fn_substring_graphemes);
         bind_native!("substringBefore", 2, fn_substring_before);
         bind_native!("substringAfter", 2, fn_substring_after);
         bind_native!("sum", 1, fn_sum);
         bind_native!("trim", 1, fn_trim);
+        bind_native!("type", 1, fn_type);
         bind_native!("uppercase", 1, fn_uppercase);
         bind_native!("zip", 1, fn_zip);
+        bind_native!("zipLongest", 2, fn_zip_longest);
+
+        if self.environment_function {
+            bind_native!("environment", 0, fn_environment);
+        }
 
         let chain_ast = Some(parser::parse(
             "function($f, $g) { function($x){ $g($f($x)) } }",
         )?);
-        let evaluator = Evaluator::new(chain_ast, self.arena, max_depth, time_limit);
-        evaluator.evaluate(&self.ast, input, &self.frame)
+        let mut evaluator = Evaluator::new(chain_ast, self.arena, max_depth, time_limit);
+        evaluator.set_lenient_comparisons(self.lenient_comparisons);
+        let result = evaluator.evaluate(&self.ast, input, &self.frame)?;
+
+        if self.undefined_as_null && result.is_undefined() {
+            Ok(Value::null(self.arena))
+        } else {
+            Ok(result)
+        }
     }
 }
 
@@ -216,7 +289,7 @@ mod tests {
         let jsonata = JsonAta::new("$map([1,4,9,16], $squareroot)", &arena).unwrap();
         jsonata.register_function("squareroot", 1, |ctx, args| {
             let num = &args[0];
-            return Ok(Value::number(ctx.arena, (num.as_f64()).sqrt()));
+            Ok(Value::number(ctx.arena, (num.as_f64()).sqrt()))
         });
 
         let result = jsonata.evaluate(Some(r#"anything"#), None);
@@ -237,7 +310,7 @@ mod tests {
         let jsonata = JsonAta::new("$filter([1,4,9,16], $even)", &arena).unwrap();
         jsonata.register_function("even", 1, |_ctx, args| {
             let num = &args[0];
-            return Ok(Value::bool((num.as_f64()) % 2.0 == 0.0));
+            Ok(Value::bool((num.as_f64()) % 2.0 == 0.0))
         });
 
         let result = jsonata.evaluate(Some(r#"anything"#), None);
@@ -252,6 +325,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn register_function_apply_with_args() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("7 ~> $between(1, 10)", &arena).unwrap();
+        jsonata.register_function("between", 3, |_ctx, args| {
+            let (n, low, high) = (args[0].as_f64(), args[1].as_f64(), args[2].as_f64());
+            Ok(Value::bool(low <= n && n <= high))
+        });
+
+        let result = jsonata.evaluate(None, None);
+
+        assert!(result.unwrap().as_bool());
+    }
+
     #[test]
     fn evaluate_with_bindings_simple() {
         let arena = Bump::new();
@@ -384,7 +471,7 @@ mod tests {
             // We expect a T0410ArgumentNotValid error
             assert_eq!(
                 err.to_string(),
-                "T0410 @ 2: Argument 1 of function now does not match function signature"
+                "T0410 @ 0: Argument 2 of function now does not match function signature"
             );
         }
     }
@@ -491,7 +578,7 @@ mod tests {
         let error = result.unwrap_err();
         assert_eq!(
             error.to_string(),
-            "T0410 @ 2: Argument 1 of function now does not match function signature"
+            "T0410 @ 0: Argument 2 of function now does not match function signature"
         );
     }
 
@@ -508,7 +595,7 @@ mod tests {
         if let Err(err) = result {
             assert_eq!(
                 err.to_string(),
-                "T0410 @ 2: Argument 1 of function now does not match function signature"
+                "T0410 @ 0: Argument 2 of function now does not match function signature"
             );
         }
     }
@@ -526,7 +613,7 @@ mod tests {
         if let Err(err) = result {
             assert_eq!(
                 err.to_string(),
-                "T0410 @ 2: Argument 1 of function now does not match function signature"
+                "T0410 @ 0: Argument 2 of function now does not match function signature"
             );
         }
     }
@@ -711,4 +798,952 @@ mod tests {
         // Since the array contains only one element "data", it should return "data"
         assert_eq!(result.as_str(), "data"); // Expecting the string "data" as the result
     }
+
+    #[test]
+    fn arithmetic_type_error_points_at_operator() {
+        let arena = Bump::new();
+
+        for (expr, expected) in [
+            (
+                "$x + false",
+                "T2002 @ 3: The right side of the `+` operator must evaluate to a number",
+            ),
+            (
+                "false - $x",
+                "T2001 @ 6: The left side of the `-` operator must evaluate to a number",
+            ),
+            (
+                "'a' * nothing",
+                "T2001 @ 4: The left side of the `*` operator must evaluate to a number",
+            ),
+            (
+                "nothing / 'a'",
+                "T2002 @ 8: The right side of the `/` operator must evaluate to a number",
+            ),
+            (
+                "1 % true",
+                "T2002 @ 2: The right side of the `%` operator must evaluate to a number",
+            ),
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let err = jsonata.evaluate(None, None).unwrap_err();
+            assert_eq!(err.to_string(), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn arithmetic_with_empty_arrays() {
+        let arena = Bump::new();
+        let input = r#"{"items": []}"#;
+
+        // An empty array is a value rather than an undefined sequence, so it's never treated as
+        // zero or skipped, but is a type error
+        for op in ["+", "-", "*", "/", "%"] {
+            for (expr, code) in [
+                (format!("([].x) {} 1", op), "T2001"),
+                (format!("items {} 1", op), "T2001"),
+                (format!("1 {} [].x", op), "T2002"),
+                (format!("1 {} items", op), "T2002"),
+            ] {
+                let jsonata = JsonAta::new(&expr, &arena).unwrap();
+                let err = jsonata.evaluate(Some(input), None).unwrap_err();
+                assert_eq!(err.code(), code, "{}", expr);
+            }
+
+            // Whereas a missing operand makes the result undefined
+            let jsonata = JsonAta::new(&format!("items.x {} 1", op), &arena).unwrap();
+            assert!(jsonata.evaluate(Some(input), None).unwrap().is_undefined());
+        }
+    }
+
+    #[test]
+    fn navigate_result() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(r#"{"a": [{"b": 1}, {"b": 2}]}"#, &arena).unwrap();
+        let result = jsonata.evaluate(None, None).unwrap();
+
+        assert_eq!(*result.get("a").index(1).get("b"), 2_usize);
+        assert!(result.get("a").index(2).is_undefined());
+        assert!(result.get("a").index(2).get("b").is_undefined());
+        assert!(result.get("missing").get("b").is_undefined());
+        assert!(result.index(0).is_undefined());
+    }
+
+    #[test]
+    fn root_context() {
+        let arena = Bump::new();
+        let input =
+            r#"{"config": {"rate": 2}, "orders": [{"items": [{"price": 1}, {"price": 5}]}]}"#;
+
+        for (expr, expected) in [
+            ("orders.items.(price * $$.config.rate)", "[2,10]"),
+            (
+                "orders.items.{'total': price * $$.config.rate}.total",
+                "[2,10]",
+            ),
+            (
+                "orders.items.$map(price, function($p) { $p * $$.config.rate })",
+                "[2,10]",
+            ),
+            ("orders.items[price > $$.config.rate].price", "5"),
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let result = jsonata.evaluate(Some(input), None).unwrap();
+            assert_eq!(result.serialize(false), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn lambda_captures_frame() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("($y := 10; function($x) { $x + $y })", &arena).unwrap();
+        let result = jsonata.evaluate(None, None).unwrap();
+
+        assert!(result.is_function());
+        assert_eq!(result.arity(), 1);
+        match result {
+            Value::Lambda { frame, .. } => {
+                assert_eq!(frame.lookup("y").map(|y| y.as_f64()), Some(10.0));
+                assert!(frame.lookup("x").is_none());
+            }
+            _ => panic!("Expected a lambda"),
+        }
+    }
+
+    #[test]
+    fn lambda_closure() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(
+            "($f := ($y := 10; function($x) { $x + $y }); $y := 20; $f(1))",
+            &arena,
+        )
+        .unwrap();
+        let result = jsonata.evaluate(None, None).unwrap();
+
+        assert_eq!(result.as_f64(), 11.0);
+    }
+
+    #[test]
+    fn variables_and_context() {
+        let arena = Bump::new();
+        let input = r#"{"field": {"inner": 2}}"#;
+
+        for (expr, expected) in [
+            ("($x := 5; $x)", Some("5")),
+            ("$y", None),
+            ("$field", None),
+            ("$.field.inner", Some("2")),
+            ("($field := 1; $.field.inner + $field)", Some("3")),
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let result = jsonata.evaluate(Some(input), None).unwrap();
+            match expected {
+                Some(expected) => assert_eq!(result.serialize(false), expected, "{}", expr),
+                None => assert!(result.is_undefined(), "{}", expr),
+            }
+        }
+    }
+
+    #[test]
+    fn typed_accessors() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(r#"{"n": 1.5, "s": "text", "a": [1, 2]}"#, &arena).unwrap();
+        let result = jsonata.evaluate(None, None).unwrap();
+
+        assert_eq!(result.as_object().map(|o| o.len()), Some(3));
+        assert_eq!(result.get("n").try_as_f64(), Some(1.5));
+        assert_eq!(result.get("s").try_as_str(), Some("text"));
+        let array = result.get("a").as_array().unwrap();
+        assert_eq!(array.len(), 2);
+        assert_eq!(array[1].try_as_f64(), Some(2.0));
+
+        assert!(result.as_array().is_none());
+        assert!(result.get("n").try_as_str().is_none());
+        assert!(result.get("s").try_as_f64().is_none());
+        assert!(result.get("a").as_object().is_none());
+        assert!(result.get("missing").as_array().is_none());
+        assert!(result.get("missing").try_as_f64().is_none());
+    }
+
+    #[test]
+    fn sum_is_compensated() {
+        let arena = Bump::new();
+
+        let jsonata = JsonAta::new("$sum([1..10000].(0.1))", &arena).unwrap();
+        let result = jsonata.evaluate(None, None).unwrap();
+        assert!((result.as_f64() - 1000.0).abs() < 1e-12);
+
+        let jsonata = JsonAta::new("$sum([1e100, 1, -1e100])", &arena).unwrap();
+        let result = jsonata.evaluate(None, None).unwrap();
+        assert_eq!(result.as_f64(), 1.0);
+    }
+
+    #[test]
+    fn evaluate_value_built_on_host() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("orders[price > 10].name", &arena).unwrap();
+
+        let order = |name, price| {
+            Value::object_of(
+                &arena,
+                [
+                    ("name", &*Value::string(&arena, name)),
+                    ("price", &*Value::number(&arena, price)),
+                ],
+            )
+        };
+        let orders = Value::array_of(&arena, [&*order("a", 5), order("b", 15), order("c", 25)]);
+        let input = Value::object_of(&arena, [("orders", &*orders)]);
+
+        let result = jsonata.evaluate_value(input).unwrap();
+
+        assert_eq!(
+            result.members().map(|v| v.as_str()).collect::<Vec<_>>(),
+            vec!["b", "c"]
+        );
+    }
+
+    #[test]
+    fn undefined_as_null() {
+        let arena = Bump::new();
+        let mut jsonata = JsonAta::new("missing", &arena).unwrap();
+
+        let result = jsonata.evaluate(Some("{}"), None).unwrap();
+        assert!(result.is_undefined());
+        assert_eq!(result.serialize(false), "");
+
+        jsonata.set_undefined_as_null(true);
+        let result = jsonata.evaluate(Some("{}"), None).unwrap();
+        assert!(result.is_null());
+        assert_eq!(result.serialize(false), "null");
+
+        // Only the top-level result is affected
+        let mut jsonata = JsonAta::new("[1, missing, 2]", &arena).unwrap();
+        jsonata.set_undefined_as_null(true);
+        let result = jsonata.evaluate(Some("{}"), None).unwrap();
+        assert_eq!(result.serialize(false), "[1,2]");
+    }
+
+    #[test]
+    fn lenient_comparisons() {
+        let arena = Bump::new();
+
+        for (expr, code) in [("null < 1", "T2010"), ("1 >= \"1\"", "T2009")] {
+            let mut jsonata = JsonAta::new(expr, &arena).unwrap();
+            let error = jsonata.evaluate(None, None).unwrap_err();
+            assert_eq!(error.code(), code, "{}", expr);
+
+            jsonata.set_lenient_comparisons(true);
+            let result = jsonata.evaluate(None, None).unwrap();
+            assert!(result.is_undefined(), "{}", expr);
+        }
+
+        // Comparable operands are unaffected
+        let mut jsonata = JsonAta::new("[1 < 2, \"b\" <= \"a\"]", &arena).unwrap();
+        jsonata.set_lenient_comparisons(true);
+        let result = jsonata.evaluate(None, None).unwrap();
+        assert_eq!(result.serialize(false), "[true,false]");
+    }
+
+    #[test]
+    fn function_argument_error_positions() {
+        let arena = Bump::new();
+
+        for (expr, code, position) in [
+            ("$substring(1, 2)", "T0410", 0),
+            ("  $map([1], 1)", "T0410", 2),
+            ("x + $sum(\"a\")", "T0412", 4),
+            ("a.$join([1, 2])", "T0412", 2),
+            ("[1, $now(\"\", 5)]", "T0410", 4),
+            ("$fromMillis(0, \"[Y]\", \"bad\")", "T0410", 0),
+            ("$string($map([1], 1))", "T0410", 8),
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let err = jsonata.evaluate(Some("{\"a\": {}}"), None).unwrap_err();
+            assert_eq!(err.code(), code, "{}", expr);
+            assert_eq!(err.position(), Some(position), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn assert_function() {
+        let arena = Bump::new();
+
+        let jsonata = JsonAta::new("($assert(a = 1, \"a is 1\"); a)", &arena).unwrap();
+        let result = jsonata.evaluate(Some("{\"a\": 1}"), None).unwrap();
+        assert_eq!(result.as_f64(), 1.0);
+
+        let jsonata = JsonAta::new("a + $assert(a > 1, \"a is too small\")", &arena).unwrap();
+        let err = jsonata.evaluate(Some("{\"a\": 1}"), None).unwrap_err();
+        assert_eq!(err.to_string(), "D3141 @ 4: a is too small");
+
+        // The message is checked even when the assertion holds
+        let jsonata = JsonAta::new("$assert(true, 5)", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert_eq!(err.code(), "T0410");
+        assert!(err.to_string().contains("Argument 2"), "{}", err);
+    }
+
+    #[test]
+    fn structured_error() {
+        let arena = Bump::new();
+
+        let jsonata = JsonAta::new(
+            "items[qty = 0] ~> $map(function($i) { $error('Out of stock', 'E_STOCK', {'sku': $i.sku}) })",
+            &arena,
+        )
+        .unwrap();
+        let err = jsonata
+            .evaluate(
+                Some(r#"{"items": [{"sku": "a", "qty": 1}, {"sku": "b", "qty": 0}]}"#),
+                None,
+            )
+            .unwrap_err();
+        assert_eq!(err.code(), "E_STOCK");
+        assert_eq!(err.to_string(), "E_STOCK @ 38: Out of stock");
+        assert_eq!(err.kind(), ErrorKind::User);
+        match err {
+            Error::D3137UserError(_, message, Some(code), Some(data)) => {
+                assert_eq!(message, "Out of stock");
+                assert_eq!(code, "E_STOCK");
+                assert_eq!(data, serde_json::json!({"sku": "b"}));
+            }
+            _ => panic!("Expected a structured error: {:?}", err),
+        }
+
+        // Without a code, the error keeps its own
+        let jsonata = JsonAta::new("$error('Failed', undefined, {'retry': true})", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert_eq!(err.code(), "D3137");
+        assert_eq!(err.kind(), ErrorKind::Dynamic);
+        assert!(matches!(err, Error::D3137UserError(0, _, None, Some(_))));
+
+        // The data keeps its numbers and nesting, and functions become empty strings
+        let jsonata = JsonAta::new(
+            "$error('Failed', 'E', {'n': 1, 'f': 1.5, 'a': [1, 'b', null, {'t': true}], 'fn': $sum})",
+            &arena,
+        )
+        .unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        let Error::D3137UserError(_, _, _, Some(data)) = err else {
+            panic!("Expected a structured error with data: {:?}", err);
+        };
+        assert_eq!(
+            data,
+            serde_json::json!({"n": 1, "f": 1.5, "a": [1, "b", null, {"t": true}], "fn": ""})
+        );
+
+        // A code can't be empty
+        let jsonata = JsonAta::new("$error('Failed', '')", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert_eq!(err.code(), "T0410");
+
+        // And a message alone is still a plain error
+        let jsonata = JsonAta::new("$error('Failed')", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert_eq!(err, Error::D3137Error("Failed".to_string()));
+    }
+
+    #[test]
+    fn sum_overflow() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("1 + $sum([1e308, 1e308])", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert_eq!(err, Error::D1001NumberOfOutRange(4, f64::INFINITY));
+    }
+
+    #[test]
+    fn callback_errors_propagate() {
+        let arena = Bump::new();
+
+        // Dividing by zero is only an error once the infinite result is used
+        for expr in [
+            "$map(xs, function($x) { 1 / $x + 1 })",
+            "$filter(xs, function($x) { 1 / $x + 1 > 0 })",
+            "$single(xs, function($x) { 1 / $x + 1 > 2 })",
+            "$reduce(xs, function($acc, $x) { $acc + 1 / $x + 1 })",
+            "$sort(xs, function($a, $b) { 1 / $a + 1 > 1 / $b + 1 })",
+            "$each({'a': 0}, function($v) { 1 / $v + 1 })",
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let err = jsonata
+                .evaluate(Some(r#"{"xs": [2, 1, 0]}"#), None)
+                .unwrap_err();
+            assert_eq!(err.code(), "D1001", "{}", expr);
+        }
+
+        let jsonata =
+            JsonAta::new("$map([1], function($x) { $error('in callback') })", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert_eq!(err, Error::D3137Error("in callback".to_string()));
+    }
+
+    #[test]
+    fn non_string_object_keys() {
+        let arena = Bump::new();
+
+        for (expr, message) in [
+            (
+                "{1: \"one\"}",
+                "T1003 @ 0: Key in object structure must evaluate to a string; got: 1",
+            ),
+            (
+                "{[1, \"a\"]: \"one\"}",
+                "T1003 @ 0: Key in object structure must evaluate to a string; got: [1,\"a\"]",
+            ),
+            (
+                "{nothing: \"one\"}",
+                "T1003 @ 0: Key in object structure must evaluate to a string; got: undefined",
+            ),
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let err = jsonata.evaluate(Some("{}"), None).unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn chained_comparison() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("1 < 2 < 3", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+
+        // `1 < 2` is a boolean, which the second `<` can't compare
+        assert_eq!(err.code(), "T2010");
+        assert_eq!(err.position(), Some(6));
+        assert_eq!(
+            err.to_string(),
+            "T2010 @ 6: The expressions either side of operator `<` must evaluate to numeric or string values"
+        );
+    }
+
+    #[test]
+    fn environment_function() {
+        let arena = Bump::new();
+        let expr = r#"(
+            $a := 1;
+            $b := "outer";
+            $f := function($x) { $x };
+            (
+                $b := ["inner"];
+                $environment()
+            )
+        )"#;
+
+        let jsonata = JsonAta::new(expr, &arena).unwrap();
+        let err = jsonata.evaluate(Some("{}"), None).unwrap_err();
+        assert_eq!(err.code(), "T1006");
+
+        let mut jsonata = JsonAta::new(expr, &arena).unwrap();
+        jsonata.set_environment_function(true);
+        jsonata.assign_var("bound", Value::bool(true));
+        let result = jsonata.evaluate(Some("{}"), None).unwrap();
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.get("a").as_f64(), 1.0);
+        assert_eq!(result.get("b").serialize(false), r#"["inner"]"#);
+        assert_eq!(result.get("bound"), Value::bool(true));
+        assert!(result.get("f").is_undefined());
+        assert!(result.get("environment").is_undefined());
+    }
+
+    #[test]
+    fn parse_without_evaluating() {
+        let ast = parse("a.b").unwrap();
+        assert!(matches!(ast.kind, AstKind::Path(ref steps) if steps.len() == 2));
+
+        let ast = parse("1 + 2").unwrap();
+        assert!(matches!(ast.kind, AstKind::Binary(BinaryOp::Add, ..)));
+
+        let ast = parse("$sum([1, 2])").unwrap();
+        assert!(matches!(ast.kind, AstKind::Function { ref name, .. } if name == "sum"));
+
+        let ast = parse("-x").unwrap();
+        assert!(matches!(ast.kind, AstKind::Unary(UnaryOp::Minus(..))));
+
+        let err = parse("1 2").unwrap_err();
+        assert_eq!(err.code(), "S0201");
+    }
+
+    #[test]
+    fn aggregate_path_results() {
+        let arena = Bump::new();
+        let input = r#"{
+            "items": [{"price": 1.5, "qty": 2}, {"price": 2.5, "qty": 1}, {"price": 4}],
+            "single": [{"price": 3}]
+        }"#;
+
+        for (expr, expected) in [
+            ("$sum(items.price)", "8"),
+            ("$count(items.price)", "3"),
+            ("$max(items.price)", "4"),
+            ("$min(items.price)", "1.5"),
+            ("$count(items.qty)", "2"),
+            ("$sum(items.(price * qty))", "5.5"),
+            ("$sum(single.price)", "3"),
+            ("$count(single.price)", "1"),
+            ("$count(missing.price)", "0"),
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let result = jsonata.evaluate(Some(input), None).unwrap();
+            assert_eq!(result.serialize(false), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert!(JsonAta::validate("Account.Order[0].Product.(Price * Quantity)").is_ok());
+        assert!(JsonAta::validate("$undefinedFunction(missing)").is_ok());
+
+        for (expr, code) in [
+            ("1 2", "S0201"),
+            ("(1 + 2", "S0203"),
+            ("\"unterminated", "S0101"),
+            ("1 +", "S0211"),
+            ("/* comment", "S0106"),
+        ] {
+            let err = JsonAta::validate(expr).unwrap_err();
+            assert_eq!(err.code(), code, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn reverse_and_sort_share_members() {
+        let arena = Bump::new();
+        let item = |n: i64| -> &Value {
+            Value::object_of(
+                &arena,
+                [
+                    ("n", n.into_value(&arena)),
+                    ("tags", vec![String::from("x")].into_value(&arena)),
+                ],
+            )
+        };
+        let items: &Value = Value::array_of(&arena, [item(2), item(3), item(1)]);
+        let input = Value::object_of(&arena, [("items", items)]);
+
+        for (expr, order) in [
+            ("$reverse(items)", [2, 1, 0]),
+            ("$sort(items, function($l, $r) { $l.n > $r.n })", [2, 0, 1]),
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let result = jsonata.evaluate_value(input).unwrap();
+
+            assert_eq!(result.len(), 3, "{}", expr);
+            for (index, original) in order.into_iter().enumerate() {
+                // The members are the input's own values, reordered rather than copied
+                assert!(
+                    std::ptr::eq(result.get_member(index), items.get_member(original)),
+                    "{}",
+                    expr
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn evaluate_value_built_with_into_value() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(
+            "[$count(tags), name & \": \" & $string(active), $sum(scores), $exists(nickname)]",
+            &arena,
+        )
+        .unwrap();
+
+        let input = Value::object_of(
+            &arena,
+            [
+                ("name", "widget".into_value(&arena)),
+                ("active", true.into_value(&arena)),
+                (
+                    "tags",
+                    vec![String::from("a"), String::from("b")].into_value(&arena),
+                ),
+                (
+                    "scores",
+                    vec![Some(1.5), None, Some(2.5)].into_value(&arena),
+                ),
+                ("count", 3_i64.into_value(&arena)),
+                ("nickname", None::<&str>.into_value(&arena)),
+            ],
+        );
+
+        let result = jsonata.evaluate_value(input).unwrap();
+
+        assert_eq!(result.serialize(false), r#"[2,"widget: true",4,false]"#);
+    }
+
+    #[test]
+    fn typed_input_and_output() {
+        #[derive(Debug, PartialEq)]
+        struct Item {
+            name: String,
+            price: f64,
+            tags: Vec<String>,
+        }
+
+        impl<'a> IntoValue<'a> for &Item {
+            fn into_value(self, arena: &'a Bump) -> &'a Value<'a> {
+                Value::object_of(
+                    arena,
+                    [
+                        ("name", self.name.as_str().into_value(arena)),
+                        ("price", self.price.into_value(arena)),
+                        ("tags", self.tags.clone().into_value(arena)),
+                    ],
+                )
+            }
+        }
+
+        impl<'a> FromValue<'a> for Item {
+            fn from_value(value: &'a Value<'a>) -> Option<Self> {
+                Some(Item {
+                    name: String::from_value(value.get("name"))?,
+                    price: f64::from_value(value.get("price"))?,
+                    tags: Vec::from_value(value.get("tags"))?,
+                })
+            }
+        }
+
+        let arena = Bump::new();
+        let items = [
+            Item {
+                name: "widget".to_string(),
+                price: 2.5,
+                tags: vec!["small".to_string()],
+            },
+            Item {
+                name: "gadget".to_string(),
+                price: 10.0,
+                tags: vec!["large".to_string(), "new".to_string()],
+            },
+        ];
+        let input: &Value = Value::array_of(&arena, items.iter().map(|i| i.into_value(&arena)));
+
+        // A struct out, with a single tag collapsed from a sequence back into a `Vec`
+        let jsonata = JsonAta::new(
+            "$[price > 5].{'name': $uppercase(name), 'price': price * 2, 'tags': tags[0]}",
+            &arena,
+        )
+        .unwrap();
+        let result = jsonata.evaluate_value(input).unwrap();
+        assert_eq!(
+            Item::from_value(result),
+            Some(Item {
+                name: "GADGET".to_string(),
+                price: 20.0,
+                tags: vec!["large".to_string()],
+            })
+        );
+
+        // Collections of primitives
+        let jsonata = JsonAta::new("name", &arena).unwrap();
+        let result = jsonata.evaluate_value(input).unwrap();
+        assert_eq!(
+            Vec::<String>::from_value(result),
+            Some(vec!["widget".to_string(), "gadget".to_string()])
+        );
+
+        let jsonata = JsonAta::new("$.{name: $count(tags)} ~> $merge", &arena).unwrap();
+        let result = jsonata.evaluate_value(input).unwrap();
+        let counts = HashMap::<String, i64>::from_value(result).unwrap();
+        assert_eq!(counts["widget"], 1);
+        assert_eq!(counts["gadget"], 2);
+
+        let prices = HashMap::from([("widget", 2.5), ("gadget", 10.0)]).into_value(&arena);
+        let jsonata = JsonAta::new("$sum(*)", &arena).unwrap();
+        let result = jsonata.evaluate_value(prices).unwrap();
+        assert_eq!(f64::from_value(result), Some(12.5));
+
+        // Conversions check the type, and undefined is an empty `Vec` or `None`
+        assert_eq!(i64::from_value(Value::number(&arena, 1.5)), None);
+        assert_eq!(bool::from_value(Value::string(&arena, "true")), None);
+        assert_eq!(Vec::<f64>::from_value(Value::undefined()), Some(vec![]));
+        assert_eq!(Option::<f64>::from_value(Value::null(&arena)), Some(None));
+        assert_eq!(Item::from_value(Value::object(&arena)), None);
+    }
+
+    #[test]
+    fn match_on_error() {
+        let arena = Bump::new();
+
+        let jsonata = JsonAta::new("'a' + 1", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert!(matches!(err, Error::T2001LeftSideNotNumber(4, ref op) if op == "+"));
+        assert_eq!(err.kind(), ErrorKind::Type);
+
+        let jsonata = JsonAta::new("$sqrt(-1)", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert!(matches!(err, Error::D3060SqrtNegative(..)));
+        assert_eq!(err.kind(), ErrorKind::Dynamic);
+
+        let err = JsonAta::new("(1", &arena).err().unwrap();
+        assert!(matches!(err, Error::S0203ExpectedTokenBeforeEnd(..)));
+        assert_eq!(err.kind(), ErrorKind::Static);
+    }
+
+    #[test]
+    fn error_line_and_column() {
+        let arena = Bump::new();
+        let expr = "(\n  $x := 1;\n  $x + 'a'\n)";
+        let jsonata = JsonAta::new(expr, &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert_eq!(err.code(), "T2002");
+        assert_eq!(err.line_and_column(expr), Some((3, 6)));
+
+        let expr = "foo\n  .bar +";
+        let err = JsonAta::new(expr, &arena).err().unwrap();
+        assert_eq!(err.line_and_column(expr), Some((2, 9)));
+
+        assert_eq!(Error::U1001Timeout.line_and_column(expr), None);
+    }
+
+    #[test]
+    fn register_function_closure() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("$map([1, 2, 3], $scale)", &arena).unwrap();
+
+        let factor = 10.0;
+        let calls = std::cell::Cell::new(0);
+        jsonata.register_function("scale", 1, |ctx, args| {
+            calls.set(calls.get() + 1);
+            Ok(Value::number(ctx.arena, args[0].as_f64() * factor))
+        });
+
+        let result = jsonata.evaluate(None, None).unwrap();
+
+        assert_eq!(result.serialize(false), "[10,20,30]");
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn register_function_error() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("1 + $checked('x')", &arena).unwrap();
+
+        jsonata.register_function("checked", 1, |ctx, args| {
+            if !args[0].is_number() {
+                return Err(Error::T0410ArgumentNotValid(
+                    ctx.char_index,
+                    1,
+                    ctx.name.to_string(),
+                ));
+            }
+            Ok(args[0])
+        });
+
+        let err = jsonata.evaluate(None, None).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "T0410 @ 4: Argument 1 of function checked does not match function signature"
+        );
+    }
+
+    #[test]
+    fn sort_mixed_types_error_position() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new("($a := [1, 'a', 2]; $sort($a))", &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+
+        assert_eq!(err.code(), "D3070");
+        assert_eq!(err.position(), Some(20));
+    }
+
+    #[test]
+    fn evaluate_value_matches_evaluate() {
+        let arena = Bump::new();
+
+        let items = Value::array(&arena, ArrayFlags::empty());
+        for i in 0..1000u32 {
+            items.push(Value::object_of(
+                &arena,
+                [
+                    ("id", &*Value::number(&arena, i)),
+                    (
+                        "group",
+                        Value::string(&arena, ["a", "b", "c"][i as usize % 3]),
+                    ),
+                ],
+            ));
+        }
+        let input = Value::object_of(&arena, [("items", &*items)]);
+        let input_json = input.serialize(false);
+
+        for expr in [
+            "items[id % 7 = 0].id",
+            "$sum(items.id)",
+            "items{group: $count(id)}",
+            "$sort(items, function($l, $r) { $l.group > $r.group })[[0..4]].id",
+            "$",
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let from_str = jsonata.evaluate(Some(&input_json), None).unwrap();
+            let from_value = jsonata.evaluate_value(input).unwrap();
+            assert_eq!(from_str, from_value, "{}", expr);
+        }
+
+        assert_eq!(input.serialize(false), input_json);
+    }
+
+    #[test]
+    fn function_arity() {
+        let arena = Bump::new();
+
+        for (expr, arity) in [
+            ("$substring", 3),
+            ("function($x, $y) { $x + $y }", 2),
+            ("$substring(?, 0, ?)", 2),
+            ("$substring(?, 0, ?)(?, 5)", 1),
+            ("function($x, $y, $z) { $x }(1, ?, ?)", 2),
+        ] {
+            let jsonata = JsonAta::new(expr, &arena).unwrap();
+            let result = jsonata.evaluate(None, None).unwrap();
+            assert!(result.is_function(), "{}", expr);
+            assert_eq!(result.arity(), arity, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn transform_does_not_modify_input() {
+        let arena = Bump::new();
+        let input = JsonAta::new("$", &arena)
+            .unwrap()
+            .evaluate(
+                Some(r#"{"a": {"b": {"x": 1, "y": 1}, "c": {"z": 1}}, "d": [{"e": 1}]}"#),
+                None,
+            )
+            .unwrap();
+        let before = input.serialize(false);
+
+        let jsonata = JsonAta::new(r#"$ ~> |a.b|{"x": 2}, ["y"]|"#, &arena).unwrap();
+        let result = jsonata.evaluate_value(input).unwrap();
+
+        assert_eq!(input.serialize(false), before);
+        assert_eq!(result.get("a").get("b").serialize(false), r#"{"x":2}"#);
+
+        // Only the path down to the match is copied
+        assert!(!std::ptr::eq(result, input));
+        assert!(!std::ptr::eq(result.get("a"), input.get("a")));
+        assert!(std::ptr::eq(
+            result.get("a").get("c"),
+            input.get("a").get("c")
+        ));
+        assert!(std::ptr::eq(result.get("d"), input.get("d")));
+    }
+
+    #[test]
+    fn transform_does_not_modify_variables() {
+        let arena = Bump::new();
+        let jsonata = JsonAta::new(
+            r#"($o := {"a": [{"b": 1}, {"b": 2}]}; [$o ~> |a|{"c": 3}|, $o])"#,
+            &arena,
+        )
+        .unwrap();
+        let result = jsonata.evaluate(None, None).unwrap();
+        let expected = JsonAta::new(
+            r#"[{"a": [{"b": 1, "c": 3}, {"b": 2, "c": 3}]}, {"a": [{"b": 1}, {"b": 2}]}]"#,
+            &arena,
+        )
+        .unwrap()
+        .evaluate(None, None)
+        .unwrap();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn boolean_casts_agree() {
+        let arena = Bump::new();
+
+        for (value, expected) in [
+            ("{}", false),
+            (r#"{"a": 1}"#, true),
+            ("function() { true }", false),
+            ("$sum", false),
+            ("null", false),
+            ("[]", false),
+            (r#"[null, ""]"#, false),
+            (r#"[null, "a"]"#, true),
+            ("\"\"", false),
+            ("\"a\"", true),
+        ] {
+            let expected = Value::bool(expected);
+            for expr in [
+                format!("$boolean({})", value),
+                format!("({}) ? true : false", value),
+                format!("$not($not({}))", value),
+                format!("$exists([true][{}])", value),
+            ] {
+                let jsonata = JsonAta::new(&expr, &arena).unwrap();
+                let result = jsonata.evaluate(None, None).unwrap();
+                assert_eq!(result, expected, "{}", expr);
+            }
+        }
+    }
+
+    #[test]
+    fn object_insert_overwrites() {
+        let arena = Bump::new();
+        let object = Value::object(&arena);
+        object.insert("a", Value::number(&arena, 1));
+        object.insert("a", Value::number(&arena, 2));
+        assert_eq!(object.entries().count(), 1);
+        assert_eq!(object.get("a"), Value::number(&arena, 2));
+    }
+
+    #[test]
+    fn object_duplicate_keys() {
+        let arena = Bump::new();
+        let input = r#"{"items": [{"k": "x", "v": 1}, {"k": "x", "v": 2}, {"k": "y", "v": 3}]}"#;
+
+        // The same key pair matching several items accumulates their values
+        let jsonata = JsonAta::new("items{k: v}", &arena).unwrap();
+        let result = jsonata.evaluate(Some(input), None).unwrap();
+        assert_eq!(result.entries().count(), 2);
+        assert_eq!(result.get("x").serialize(false), "[1,2]");
+        assert_eq!(result.get("y").serialize(false), "3");
+
+        // ...but two different pairs producing the same key is an error
+        let jsonata = JsonAta::new(r#"{"a": 1, "a": 2}"#, &arena).unwrap();
+        let err = jsonata.evaluate(None, None).unwrap_err();
+        assert_eq!(err.code(), "D1009");
+    }
+
+    #[test]
+    fn ast_accessor() {
+        fn count_binary_ops(ast: &Ast) -> usize {
+            let children = match ast.kind {
+                AstKind::Binary(_, ref lhs, ref rhs) => {
+                    1 + count_binary_ops(lhs) + count_binary_ops(rhs)
+                }
+                AstKind::Unary(UnaryOp::Minus(ref operand)) | AstKind::Filter(ref operand) => {
+                    count_binary_ops(operand)
+                }
+                AstKind::Unary(UnaryOp::ArrayConstructor(ref items))
+                | AstKind::Block(ref items)
+                | AstKind::Path(ref items)
+                | AstKind::Function {
+                    args: ref items, ..
+                } => items.iter().map(count_binary_ops).sum(),
+                _ => 0,
+            };
+            children
+                + ast
+                    .stages
+                    .iter()
+                    .chain(ast.predicates.iter())
+                    .flatten()
+                    .map(count_binary_ops)
+                    .sum::<usize>()
+        }
+
+        let arena = Bump::new();
+        let jsonata =
+            JsonAta::new("a + b * (c - d) > 3 and $sum([e / 2, -f.g[h = 1]])", &arena).unwrap();
+        assert_eq!(count_binary_ops(jsonata.ast()), 7);
+
+        let jsonata = JsonAta::new("a.b.c", &arena).unwrap();
+        assert_eq!(count_binary_ops(jsonata.ast()), 0);
+        assert!(matches!(jsonata.ast().kind, AstKind::Path(ref steps) if steps.len() == 3));
+    }
 }
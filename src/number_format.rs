@@ -0,0 +1,496 @@
+// Number formatting with picture strings, as described by the XPath F&O 3.1 specification
+// for format-number (section 4.7) and implemented by the JSONata reference.
+
+use crate::Error;
+
+/// The properties of a decimal format, which can be overridden by name with the options object
+/// passed to `$formatNumber`.
+#[derive(Debug, Clone)]
+pub struct DecimalFormat {
+    decimal_separator: char,
+    grouping_separator: char,
+    exponent_separator: char,
+    minus_sign: String,
+    percent: String,
+    per_mille: String,
+    zero_digit: char,
+    digit: char,
+    pattern_separator: char,
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+            grouping_separator: ',',
+            exponent_separator: 'e',
+            minus_sign: "-".to_string(),
+            percent: "%".to_string(),
+            per_mille: "\u{2030}".to_string(),
+            zero_digit: '0',
+            digit: '#',
+            pattern_separator: ';',
+        }
+    }
+}
+
+impl DecimalFormat {
+    /// Overrides a property by its name in the options object, e.g. `per-mille`. Unknown names
+    /// and empty values are ignored.
+    pub fn set(&mut self, name: &str, value: &str) {
+        let Some(first) = value.chars().next() else {
+            return;
+        };
+
+        match name {
+            "decimal-separator" => self.decimal_separator = first,
+            "grouping-separator" => self.grouping_separator = first,
+            "exponent-separator" => self.exponent_separator = first,
+            "minus-sign" => self.minus_sign = value.to_string(),
+            "percent" => self.percent = value.to_string(),
+            "per-mille" => self.per_mille = value.to_string(),
+            "zero-digit" => self.zero_digit = first,
+            "digit" => self.digit = first,
+            "pattern-separator" => self.pattern_separator = first,
+            _ => (),
+        }
+    }
+
+    /// Whether the char is one of the ten digits starting at the zero digit.
+    fn is_decimal_digit(&self, c: char) -> bool {
+        (c as u32).wrapping_sub(self.zero_digit as u32) < 10
+    }
+
+    fn is_digit(&self, c: char) -> bool {
+        self.is_decimal_digit(c) || c == self.digit
+    }
+
+    fn is_active(&self, c: char) -> bool {
+        self.is_digit(c)
+            || c == self.decimal_separator
+            || c == self.exponent_separator
+            || c == self.grouping_separator
+            || c == self.pattern_separator
+    }
+
+    fn to_decimal_digit(&self, digit: char) -> char {
+        char::from_u32(self.zero_digit as u32 + digit.to_digit(10).unwrap()).unwrap_or(digit)
+    }
+}
+
+/// A sub-picture split into its parts. The integer and fractional parts make up the mantissa,
+/// which along with the exponent is the active part, surrounded by the passive prefix and
+/// suffix.
+struct SubPicture {
+    picture: String,
+    prefix: String,
+    suffix: String,
+    active: Vec<char>,
+    mantissa: Vec<char>,
+    exponent: Option<Vec<char>>,
+    integer: Vec<char>,
+    fractional: Vec<char>,
+}
+
+impl SubPicture {
+    fn new(picture: &str, format: &DecimalFormat) -> Self {
+        let chars: Vec<char> = picture.chars().collect();
+
+        // The prefix and suffix end at the first and last active characters, although an
+        // exponent separator is allowed in them
+        let is_boundary = |c: &char| format.is_active(*c) && *c != format.exponent_separator;
+        let (start, end) = match chars.iter().position(is_boundary) {
+            Some(start) => (start, chars.iter().rposition(is_boundary).unwrap() + 1),
+            None => (chars.len(), chars.len()),
+        };
+        let active = chars[start..end].to_vec();
+
+        let (mantissa, exponent) = match active.iter().position(|c| *c == format.exponent_separator)
+        {
+            Some(position) => (
+                active[..position].to_vec(),
+                Some(active[position + 1..].to_vec()),
+            ),
+            None => (active.clone(), None),
+        };
+
+        let (integer, fractional) =
+            match mantissa.iter().position(|c| *c == format.decimal_separator) {
+                Some(position) => (
+                    mantissa[..position].to_vec(),
+                    mantissa[position + 1..].to_vec(),
+                ),
+                None => (mantissa.clone(), vec![]),
+            };
+
+        Self {
+            picture: picture.to_string(),
+            prefix: chars[..start].iter().collect(),
+            suffix: chars[end..].iter().collect(),
+            active,
+            mantissa,
+            exponent,
+            integer,
+            fractional,
+        }
+    }
+
+    fn contains(&self, s: &str) -> bool {
+        !s.is_empty() && self.picture.contains(s)
+    }
+
+    fn contains_more_than_once(&self, s: &str) -> bool {
+        !s.is_empty() && self.picture.matches(s).count() > 1
+    }
+
+    /// Checks the rules in F&O 4.7.3. As in the JSONata reference, when several are broken the
+    /// last one is reported.
+    fn validate(&self, format: &DecimalFormat, char_index: usize) -> Result<(), Error> {
+        let mut error = None;
+        let chars: Vec<char> = self.picture.chars().collect();
+        let grouping = format.grouping_separator;
+
+        let decimal_positions: Vec<usize> = chars
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == format.decimal_separator)
+            .map(|(i, _)| i)
+            .collect();
+        if decimal_positions.len() > 1 {
+            error = Some(Error::D3081MultipleDecimalSeparators(char_index));
+        }
+        if self.contains_more_than_once(&format.percent) {
+            error = Some(Error::D3082MultiplePercents(char_index));
+        }
+        if self.contains_more_than_once(&format.per_mille) {
+            error = Some(Error::D3083MultiplePerMilles(char_index));
+        }
+        if self.contains(&format.percent) && self.contains(&format.per_mille) {
+            error = Some(Error::D3084PercentAndPerMille(char_index));
+        }
+        if !self.mantissa.iter().any(|c| format.is_digit(*c)) {
+            error = Some(Error::D3085NoMantissaDigits(char_index));
+        }
+        if self.active.iter().any(|c| !format.is_active(*c)) {
+            error = Some(Error::D3086PassiveBetweenActive(char_index));
+        }
+        if let Some(&position) = decimal_positions.first() {
+            if (position > 0 && chars[position - 1] == grouping)
+                || chars.get(position + 1) == Some(&grouping)
+            {
+                error = Some(Error::D3087GroupingAdjacentToDecimal(char_index));
+            }
+        } else if self.integer.last() == Some(&grouping) {
+            error = Some(Error::D3088GroupingAtEndOfInteger(char_index));
+        }
+        if chars
+            .windows(2)
+            .any(|w| w[0] == grouping && w[1] == grouping)
+        {
+            error = Some(Error::D3089AdjacentGroupingSeparators(char_index));
+        }
+        if let Some(position) = self.integer.iter().position(|c| *c == format.digit) {
+            if self.integer[..position]
+                .iter()
+                .any(|c| format.is_decimal_digit(*c))
+            {
+                error = Some(Error::D3090DigitBeforeOptionalDigit(char_index));
+            }
+        }
+        if let Some(position) = self.fractional.iter().rposition(|c| *c == format.digit) {
+            if self.fractional[position..]
+                .iter()
+                .any(|c| format.is_decimal_digit(*c))
+            {
+                error = Some(Error::D3091OptionalDigitBeforeDigit(char_index));
+            }
+        }
+        if let Some(ref exponent) = self.exponent {
+            if !exponent.is_empty()
+                && (self.contains(&format.percent) || self.contains(&format.per_mille))
+            {
+                error = Some(Error::D3092ExponentWithPercent(char_index));
+            }
+            if exponent.is_empty() || exponent.iter().any(|c| !format.is_decimal_digit(*c)) {
+                error = Some(Error::D3093InvalidExponent(char_index));
+            }
+        }
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The variables derived from a sub-picture in F&O 4.7.4, which drive the formatting.
+#[derive(Clone)]
+struct Analysis {
+    integer_grouping_positions: Vec<usize>,
+    regular_grouping: usize,
+    minimum_integer_size: usize,
+    scaling_factor: usize,
+    fractional_grouping_positions: Vec<usize>,
+    minimum_fractional_size: usize,
+    maximum_fractional_size: usize,
+    minimum_exponent_size: usize,
+    prefix: String,
+    suffix: String,
+    percent: bool,
+    per_mille: bool,
+}
+
+impl Analysis {
+    fn new(picture: &SubPicture, format: &DecimalFormat) -> Self {
+        let count_decimal_digits =
+            |part: &[char]| part.iter().filter(|c| format.is_decimal_digit(**c)).count();
+        let count_digits = |part: &[char]| part.iter().filter(|c| format.is_digit(**c)).count();
+
+        // Grouping positions count the digits to the right of each separator in the integer
+        // part, and to the left of each in the fractional part
+        let integer_grouping_positions: Vec<usize> = picture
+            .integer
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == format.grouping_separator)
+            .map(|(i, _)| count_digits(&picture.integer[i..]))
+            .collect();
+        let fractional_grouping_positions: Vec<usize> = picture
+            .fractional
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == format.grouping_separator)
+            .map(|(i, _)| count_digits(&picture.fractional[..i]))
+            .collect();
+
+        let mut minimum_integer_size = count_decimal_digits(&picture.integer);
+        let scaling_factor = minimum_integer_size;
+        let mut minimum_fractional_size = count_decimal_digits(&picture.fractional);
+        let mut maximum_fractional_size = count_digits(&picture.fractional);
+
+        let exponent_present = picture.exponent.is_some();
+        if minimum_integer_size == 0 && maximum_fractional_size == 0 {
+            if exponent_present {
+                minimum_fractional_size = 1;
+                maximum_fractional_size = 1;
+            } else {
+                minimum_integer_size = 1;
+            }
+        }
+        if exponent_present && minimum_integer_size == 0 && picture.integer.contains(&format.digit)
+        {
+            minimum_integer_size = 1;
+        }
+        if minimum_integer_size == 0 && minimum_fractional_size == 0 {
+            minimum_fractional_size = 1;
+        }
+
+        Self {
+            regular_grouping: regular_grouping(&integer_grouping_positions),
+            integer_grouping_positions,
+            minimum_integer_size,
+            scaling_factor,
+            fractional_grouping_positions,
+            minimum_fractional_size,
+            maximum_fractional_size,
+            minimum_exponent_size: picture.exponent.as_deref().map_or(0, count_decimal_digits),
+            prefix: picture.prefix.clone(),
+            suffix: picture.suffix.clone(),
+            percent: picture.contains(&format.percent),
+            per_mille: picture.contains(&format.per_mille),
+        }
+    }
+}
+
+/// If the grouping positions are all multiples of the same interval, with none missing, returns
+/// that interval so that grouping repeats across the whole integer part. Otherwise returns 0.
+fn regular_grouping(positions: &[usize]) -> usize {
+    fn gcd(a: usize, b: usize) -> usize {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+
+    if positions.is_empty() {
+        return 0;
+    }
+
+    let factor = positions.iter().copied().reduce(gcd).unwrap();
+    if (1..=positions.len()).all(|index| positions.contains(&(index * factor))) {
+        factor
+    } else {
+        0
+    }
+}
+
+/// Rounds half to even at the given number of decimal places, shifting the decimal point in the
+/// number's string form so that e.g. 0.125 is treated as exactly halfway.
+fn round_half_even(value: f64, precision: usize) -> f64 {
+    let shifted: f64 = format!("{}e{}", value, precision).parse().unwrap();
+
+    // Shifting a number near f64::MAX overflows, but one that large has no fractional part to round
+    if !shifted.is_finite() {
+        return value;
+    }
+
+    format!("{}e-{}", shifted.round_ties_even(), precision)
+        .parse()
+        .unwrap()
+}
+
+pub fn format_number(
+    value: f64,
+    picture: &str,
+    format: &DecimalFormat,
+    char_index: usize,
+) -> Result<String, Error> {
+    let sub_pictures: Vec<SubPicture> = picture
+        .split(format.pattern_separator)
+        .map(|sub_picture| SubPicture::new(sub_picture, format))
+        .collect();
+
+    if sub_pictures.len() > 2 {
+        return Err(Error::D3080TooManySubPictures(char_index));
+    }
+
+    for sub_picture in &sub_pictures {
+        sub_picture.validate(format, char_index)?;
+    }
+
+    let mut analyses: Vec<Analysis> = sub_pictures
+        .iter()
+        .map(|sub_picture| Analysis::new(sub_picture, format))
+        .collect();
+
+    // Without a sub-picture for negative numbers, use the positive one with a minus sign
+    if analyses.len() == 1 {
+        let mut negative = analyses[0].clone();
+        negative.prefix = format!("{}{}", format.minus_sign, negative.prefix);
+        analyses.push(negative);
+    }
+
+    let pic = if value >= 0.0 {
+        &analyses[0]
+    } else {
+        &analyses[1]
+    };
+
+    let adjusted = if pic.percent {
+        value * 100.0
+    } else if pic.per_mille {
+        value * 1000.0
+    } else {
+        value
+    };
+
+    // The sign comes from the choice of sub-picture, so only the magnitude is formatted
+    let mut mantissa = adjusted.abs();
+    let mut exponent = None;
+    if pic.minimum_exponent_size > 0 {
+        let max_mantissa = 10f64.powi(pic.scaling_factor as i32);
+        let min_mantissa = 10f64.powi(pic.scaling_factor as i32 - 1);
+        let mut e: i32 = 0;
+        if mantissa != 0.0 {
+            while mantissa < min_mantissa {
+                mantissa *= 10.0;
+                e -= 1;
+            }
+            while mantissa > max_mantissa {
+                mantissa /= 10.0;
+                e += 1;
+            }
+        }
+        exponent = Some(e);
+    }
+
+    let rounded = round_half_even(mantissa, pic.maximum_fractional_size);
+
+    let make_string = |value: f64, precision: usize| -> Vec<char> {
+        format!("{:.*}", precision, value.abs())
+            .chars()
+            .map(|c| {
+                if c.is_ascii_digit() {
+                    format.to_decimal_digit(c)
+                } else {
+                    c
+                }
+            })
+            .collect()
+    };
+
+    let zero = format.zero_digit;
+    let decimal_separator = format.decimal_separator;
+    let grouping_separator = format.grouping_separator;
+
+    let mut s = make_string(rounded, pic.maximum_fractional_size);
+    match s.iter().position(|c| *c == '.') {
+        Some(position) => s[position] = decimal_separator,
+        None => s.push(decimal_separator),
+    }
+
+    // Strip the zeros at both ends, then pad back to the minimum sizes
+    let leading = s.iter().take_while(|c| **c == zero).count();
+    s.drain(..leading);
+    let trailing = s.iter().rev().take_while(|c| **c == zero).count();
+    s.truncate(s.len() - trailing);
+
+    let decimal_position = s.iter().position(|c| *c == decimal_separator).unwrap();
+    let pad_left = pic.minimum_integer_size.saturating_sub(decimal_position);
+    let fractional_len = s.len() - decimal_position - 1;
+    let pad_right = pic.minimum_fractional_size.saturating_sub(fractional_len);
+    s.splice(0..0, std::iter::repeat_n(zero, pad_left));
+    s.extend(std::iter::repeat_n(zero, pad_right));
+
+    let mut decimal_position = s.iter().position(|c| *c == decimal_separator).unwrap();
+    // A regular grouping of 0 means there's no interval, so the explicit positions are used
+    if let Some(group_count) = decimal_position
+        .saturating_sub(1)
+        .checked_div(pic.regular_grouping)
+    {
+        for group in 1..=group_count {
+            s.insert(
+                decimal_position - group * pic.regular_grouping,
+                grouping_separator,
+            );
+        }
+    } else {
+        for position in &pic.integer_grouping_positions {
+            if *position <= decimal_position {
+                s.insert(decimal_position - position, grouping_separator);
+                decimal_position += 1;
+            }
+        }
+    }
+
+    let decimal_position = s.iter().position(|c| *c == decimal_separator).unwrap();
+    for position in &pic.fractional_grouping_positions {
+        let index = position + decimal_position + 1;
+        if index <= s.len() {
+            s.insert(index, grouping_separator);
+        }
+    }
+
+    let decimal_position = s.iter().position(|c| *c == decimal_separator).unwrap();
+    if pic.minimum_fractional_size == 0 && decimal_position == s.len() - 1 {
+        s.pop();
+    }
+
+    let mut result: String = s.into_iter().collect();
+
+    if let Some(exponent) = exponent {
+        let digits = make_string(exponent as f64, 0);
+        result.push(format.exponent_separator);
+        if exponent < 0 {
+            result.push_str(&format.minus_sign);
+        }
+        result.extend(std::iter::repeat_n(
+            zero,
+            pic.minimum_exponent_size.saturating_sub(digits.len()),
+        ));
+        result.extend(digits);
+    }
+
+    Ok(format!("{}{}{}", pic.prefix, result, pic.suffix))
+}
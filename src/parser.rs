@@ -1,12 +1,15 @@
 pub mod ast;
 pub mod expressions;
+mod format;
 mod process;
+pub mod signature;
 mod symbol;
 mod tokenizer;
 
 use crate::{Error, Result};
 
 use ast::*;
+pub use format::format_expression;
 use symbol::Symbol;
 use tokenizer::*;
 
@@ -74,9 +77,10 @@ impl<'a> Parser<'a> {
 pub fn parse(source: &str) -> Result<Ast> {
     let mut parser = Parser::new(source)?;
     let ast = parser.expression(0)?;
+    // The whole source must be a single expression, so anything left over is an error
     if !matches!(parser.token().kind, TokenKind::End) {
         return Err(Error::S0201SyntaxError(
-            parser.token().byte_index,
+            parser.token().char_index,
             parser.tokenizer.string_from_token(parser.token()),
         ));
     }
@@ -93,6 +97,7 @@ mod tests {
 
     #[test_case("Address1.City" ; "basic path")]
     #[test_case("Other.`Over 18 ?`" ; "backtick path")]
+    #[test_case("`field name`.`a.b`" ; "backtick path with dot")]
     #[test_case("Phone1[0]" ; "array index")]
     #[test_case("Phone2[-1]" ; "negative array index")]
     #[test_case("Phone3[0].Number" ; "index and path")]
@@ -273,4 +278,393 @@ mod tests {
     fn parser_tests(source: &str) {
         let _ = parse(source);
     }
+
+    #[test]
+    fn display_tree() {
+        let ast = parse("a.b + 1").unwrap();
+        assert_eq!(
+            ast.to_string(),
+            "Binary(+) @ 4\n  Path @ 0\n    Name(a) @ 0\n    Name(b) @ 2\n  Number(1) @ 6\n"
+        );
+    }
+
+    #[test]
+    fn ternary() {
+        let ast = parse("a ? b : c").unwrap();
+        assert!(matches!(
+            ast.kind,
+            AstKind::Ternary {
+                falsy: Some(..),
+                ..
+            }
+        ));
+
+        let ast = parse("a ? b").unwrap();
+        assert!(matches!(ast.kind, AstKind::Ternary { falsy: None, .. }));
+    }
+
+    #[test]
+    fn nested_ternary() {
+        // The inner ternary binds to the truthy branch
+        let ast = parse("a ? b ? 1 : 2 : 3").unwrap();
+        let AstKind::Ternary { truthy, falsy, .. } = ast.kind else {
+            panic!("Expected a ternary");
+        };
+        assert!(matches!(
+            truthy.kind,
+            AstKind::Ternary {
+                falsy: Some(..),
+                ..
+            }
+        ));
+        assert!(matches!(falsy.unwrap().kind, AstKind::Number(n) if n == 3.0));
+
+        // ...and chains through the falsy branch
+        let ast = parse("a ? 1 : b ? 2 : 3").unwrap();
+        let AstKind::Ternary { falsy, .. } = ast.kind else {
+            panic!("Expected a ternary");
+        };
+        assert!(matches!(falsy.unwrap().kind, AstKind::Ternary { .. }));
+    }
+
+    #[test]
+    fn colons_in_object_and_ternary() {
+        let ast = parse(r#"{"k": a ? b : c}"#).unwrap();
+        let AstKind::Unary(UnaryOp::ObjectConstructor(object)) = ast.kind else {
+            panic!("Expected an object constructor");
+        };
+        assert_eq!(object.len(), 1);
+        assert!(matches!(object[0].0.kind, AstKind::String(ref k) if k == "k"));
+        assert!(matches!(
+            object[0].1.kind,
+            AstKind::Ternary {
+                falsy: Some(..),
+                ..
+            }
+        ));
+
+        let ast = parse(r#"a ? {"k": 1} : {"k": 2}"#).unwrap();
+        let AstKind::Ternary { truthy, falsy, .. } = ast.kind else {
+            panic!("Expected a ternary");
+        };
+        assert!(matches!(
+            truthy.kind,
+            AstKind::Unary(UnaryOp::ObjectConstructor(..))
+        ));
+        assert!(matches!(
+            falsy.unwrap().kind,
+            AstKind::Unary(UnaryOp::ObjectConstructor(..))
+        ));
+    }
+
+    #[test]
+    fn variables_and_context() {
+        let ast = parse("$field").unwrap();
+        assert!(matches!(ast.kind, AstKind::Var(ref name) if name == "field"));
+
+        let ast = parse("$.field").unwrap();
+        let AstKind::Path(steps) = ast.kind else {
+            panic!("Expected a path");
+        };
+        assert!(matches!(steps[0].kind, AstKind::Var(ref name) if name.is_empty()));
+        assert!(matches!(steps[1].kind, AstKind::Name(ref name) if name == "field"));
+    }
+
+    #[test]
+    fn stray_colons() {
+        assert_eq!(parse("a : b").unwrap_err().code(), "S0201");
+        assert_eq!(parse(r#"{"k" b}"#).unwrap_err().code(), "S0202");
+        assert_eq!(parse("a ? b :").unwrap_err().code(), "S0211");
+    }
+
+    #[test]
+    fn trailing_tokens() {
+        for (source, position, token) in [
+            ("1 + 2 3", 6, "3"),
+            ("a b", 2, "b"),
+            ("(a) )", 4, ")"),
+            ("$f() ]", 5, "]"),
+            ("a.b }", 4, "}"),
+            ("\"é\" x", 4, "x"),
+        ] {
+            let error = parse(source).unwrap_err();
+            assert!(
+                matches!(error, Error::S0201SyntaxError(p, ref t) if p == position && t == token),
+                "{}: {}",
+                source,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn parent_operator() {
+        for (source, position) in [("%", 0), ("a.%.b", 2), ("(%)", 1)] {
+            let error = parse(source).unwrap_err();
+            assert!(
+                matches!(error, Error::X0002ParentUnsupported(p) if p == position),
+                "{}: {}",
+                source,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn blocks() {
+        for (source, len) in [("()", 0), ("(a)", 1), ("(a; b; c)", 3), ("(a; b;)", 2)] {
+            let ast = parse(source).unwrap();
+            assert!(
+                matches!(ast.kind, AstKind::Block(ref expressions) if expressions.len() == len),
+                "{}",
+                source
+            );
+        }
+
+        let ast = parse("($x := 1; $x)").unwrap();
+        let AstKind::Block(expressions) = ast.kind else {
+            panic!("Expected a block");
+        };
+        assert!(matches!(
+            expressions[0].kind,
+            AstKind::Binary(BinaryOp::Bind, ..)
+        ));
+        assert!(matches!(expressions[1].kind, AstKind::Var(ref name) if name == "x"));
+
+        assert_eq!(parse("(a;;b)").unwrap_err().code(), "S0211");
+        assert_eq!(parse("(a; b").unwrap_err().code(), "S0203");
+    }
+
+    #[test]
+    fn object_constructor_and_grouping() {
+        let ast = parse(r#"{"a": 1, "b": [2]}"#).unwrap();
+        let AstKind::Unary(UnaryOp::ObjectConstructor(object)) = ast.kind else {
+            panic!("Expected an object constructor");
+        };
+        assert_eq!(object.len(), 2);
+
+        let ast = parse("orders{ customer: items }").unwrap();
+        assert!(matches!(ast.kind, AstKind::Path(ref steps) if steps.len() == 1));
+        let (_, object) = ast.group_by.expect("Expected a grouping");
+        assert_eq!(object.len(), 1);
+        assert!(matches!(object[0].0.kind, AstKind::Path(..)));
+
+        assert_eq!(parse("a{'x': 1}{'y': 2}").unwrap_err().code(), "S0210");
+    }
+
+    #[test]
+    fn array_constructor_and_predicates() {
+        let ast = parse("[1..5, 8]").unwrap();
+        let AstKind::Unary(UnaryOp::ArrayConstructor(ref items)) = ast.kind else {
+            panic!("Expected an array constructor");
+        };
+        assert_eq!(items.len(), 2);
+        assert!(matches!(
+            items[0].kind,
+            AstKind::Binary(BinaryOp::Range, ..)
+        ));
+        assert!(matches!(items[1].kind, AstKind::Number(n) if n == 8.0));
+
+        // Predicates on a constructed array are attached to the array itself
+        let ast = parse("[1, 2, 3][$ > 1]").unwrap();
+        assert!(matches!(
+            ast.kind,
+            AstKind::Unary(UnaryOp::ArrayConstructor(..))
+        ));
+        assert_eq!(ast.predicates.map(|p| p.len()), Some(1));
+
+        // ...while in a path they become stages of the step
+        let ast = parse("foo[x > 1][0]").unwrap();
+        let AstKind::Path(ref steps) = ast.kind else {
+            panic!("Expected a path");
+        };
+        let stages = steps[0].stages.as_ref().expect("Expected stages");
+        assert_eq!(stages.len(), 2);
+        assert!(stages.iter().all(|s| matches!(s.kind, AstKind::Filter(..))));
+    }
+
+    #[test]
+    fn tilde() {
+        let ast = parse("a ~> $string()").unwrap();
+        assert!(matches!(ast.kind, AstKind::Binary(BinaryOp::Apply, ..)));
+
+        // `~` is only meaningful as part of `~>`
+        for (expr, code, position) in [
+            ("a ~ b", "S0201", 2),
+            ("~", "S0211", 0),
+            ("~ > a", "S0211", 0),
+        ] {
+            let err = parse(expr).unwrap_err();
+            assert_eq!(err.code(), code, "{}", expr);
+            assert_eq!(err.position(), Some(position), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn transforms() {
+        let ast = parse(r#"| a | {"x": 1} |"#).unwrap();
+        assert!(matches!(ast.kind, AstKind::Transform { delete: None, .. }));
+
+        let ast = parse(r#"$ ~> | a | {"x": 1}, ["y"] |"#).unwrap();
+        let AstKind::Binary(BinaryOp::Apply, _, ref rhs) = ast.kind else {
+            panic!("Expected a function application");
+        };
+        let AstKind::Transform {
+            ref pattern,
+            ref update,
+            delete: Some(ref delete),
+        } = rhs.kind
+        else {
+            panic!("Expected a transform with a delete clause");
+        };
+        assert!(matches!(pattern.kind, AstKind::Path(..)));
+        assert!(matches!(
+            update.kind,
+            AstKind::Unary(UnaryOp::ObjectConstructor(..))
+        ));
+        assert!(matches!(
+            delete.kind,
+            AstKind::Unary(UnaryOp::ArrayConstructor(..))
+        ));
+
+        assert_eq!(parse(r#"| a | {"x": 1}"#).unwrap_err().code(), "S0203");
+    }
+
+    #[test]
+    fn keyword_operators_as_names() {
+        fn is_name(ast: &Ast, expected: &str) -> bool {
+            matches!(ast.kind, AstKind::Path(ref steps)
+                if matches!(steps.last().unwrap().kind, AstKind::Name(ref n) if n == expected))
+        }
+
+        // After a `.` a keyword is just a field name
+        for (source, name) in [("data.and", "and"), ("data.or", "or"), ("foo.in", "in")] {
+            let ast = parse(source).unwrap();
+            assert!(is_name(&ast, name), "{}", source);
+        }
+
+        let ast = parse("a in b").unwrap();
+        assert!(matches!(ast.kind, AstKind::Binary(BinaryOp::In, ..)));
+
+        // In operand position a keyword is a name, in operator position it's the operator
+        let ast = parse("and and or").unwrap();
+        let AstKind::Binary(BinaryOp::And, ref lhs, ref rhs) = ast.kind else {
+            panic!("Expected `and`");
+        };
+        assert!(is_name(lhs, "and"));
+        assert!(is_name(rhs, "or"));
+
+        let ast = parse("in in [3]").unwrap();
+        let AstKind::Binary(BinaryOp::In, ref lhs, _) = ast.kind else {
+            panic!("Expected `in`");
+        };
+        assert!(is_name(lhs, "in"));
+
+        // `and` binds more tightly than `or`
+        let ast = parse("a or b and c").unwrap();
+        let AstKind::Binary(BinaryOp::Or, _, ref rhs) = ast.kind else {
+            panic!("Expected `or`");
+        };
+        assert!(matches!(rhs.kind, AstKind::Binary(BinaryOp::And, ..)));
+    }
+
+    #[test]
+    fn lambda_signatures() {
+        let ast = parse("function($x)<n:n>{ $x * 2 }").unwrap();
+        let AstKind::Lambda {
+            signature: Some(ref signature),
+            ..
+        } = ast.kind
+        else {
+            panic!("Expected a lambda with a signature: {:?}", ast.kind);
+        };
+        assert_eq!(signature.params.len(), 1);
+        assert_eq!(signature.params[0].types, "n");
+        assert_eq!(signature.result.as_deref(), Some("n"));
+
+        let ast = parse("λ($a, $s, $n)<a<s>(ns)-n+:s>{ $a }").unwrap();
+        let AstKind::Lambda {
+            signature: Some(ref signature),
+            ..
+        } = ast.kind
+        else {
+            panic!("Expected a lambda with a signature: {:?}", ast.kind);
+        };
+        let params = &signature.params;
+        assert_eq!(params.len(), 3);
+        assert_eq!(params[0].subtype.as_deref(), Some("s"));
+        assert!(params[1].types == "ns" && params[1].use_context);
+        assert!(params[2].variadic && !params[2].optional);
+        assert_eq!(signature.to_string(), "<a<s>(ns)-n+:s>");
+
+        let ast = parse("function($x){ $x }").unwrap();
+        assert!(matches!(
+            ast.kind,
+            AstKind::Lambda {
+                signature: None,
+                ..
+            }
+        ));
+
+        for (expr, code, position) in [
+            ("function($x)<n<n>>{ $x }", "S0401", 14),
+            ("function($x)<(sa<n>)>{ $x }", "S0402", 13),
+            ("function($x)<z>{ $x }", "S0201", 13),
+            ("function($x)<?n>{ $x }", "S0201", 13),
+            ("function($x)<n:>{ $x }", "S0201", 14),
+            ("function($x)<(ns>{ $x }", "S0201", 13),
+            ("function($x)<n:n", "S0203", 16),
+        ] {
+            let err = parse(expr).unwrap_err();
+            assert_eq!(err.code(), code, "{}", expr);
+            assert_eq!(err.position(), Some(position), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn regex_literals() {
+        // After an operand a `/` is division, otherwise it starts a regex
+        for source in [
+            "a / b",
+            "4 / 2",
+            "(a) / 2",
+            "a[0] / 2",
+            "$x / 2",
+            "data.and / 2",
+            "data.or / 2",
+            "data.in / 2",
+        ] {
+            let ast = parse(source).unwrap();
+            assert!(
+                matches!(ast.kind, AstKind::Binary(BinaryOp::Divide, ..)),
+                "{}",
+                source
+            );
+        }
+
+        let ast = parse("/a(b|c)+d/i").unwrap();
+        let AstKind::Regex(ref regex) = ast.kind else {
+            panic!("Expected a regex");
+        };
+        assert_eq!(regex.as_str(), "a(b|c)+d");
+        assert!(regex.is_match("ACBD"));
+
+        // `and` as an operator is still followed by an operand
+        let ast = parse("a and /b/").unwrap();
+        assert!(
+            matches!(ast.kind, AstKind::Binary(BinaryOp::And, _, ref rhs) if matches!(rhs.kind, AstKind::Regex(..)))
+        );
+
+        // Slashes inside brackets or escaped don't end the regex
+        let ast = parse(r#"$replace(s, /[/]\/x/, "")"#).unwrap();
+        let AstKind::Function { ref args, .. } = ast.kind else {
+            panic!("Expected a function call");
+        };
+        assert!(matches!(args[1].kind, AstKind::Regex(ref r) if r.as_str() == r"[/]\/x"));
+
+        assert_eq!(parse("//").unwrap_err().code(), "S0301");
+        assert_eq!(parse("/abc").unwrap_err().code(), "S0302");
+        assert_eq!(parse("/a(b/").unwrap_err().code(), "S0302");
+        assert_eq!(parse("/+a/").unwrap_err().code(), "S0303");
+    }
 }
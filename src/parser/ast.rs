@@ -1,9 +1,51 @@
+use super::signature::Signature;
+
 // Object constructor, represented by tuples of (key, value)
 pub type Object = Vec<(Ast, Ast)>;
 
 // Sort terms, representend by expresions and a bool indicating descending/ascending
 pub type SortTerms = Vec<(Ast, bool)>;
 
+/// A regular expression literal such as `/ab+c/i`, compiled when the expression is parsed.
+/// The `i` (case-insensitive) and `m` (multi-line) flags are supported.
+#[derive(Debug, Clone)]
+pub struct RegexLiteral {
+    regex: regex::Regex,
+    flags: String,
+}
+
+impl RegexLiteral {
+    pub fn new(pattern: &str, flags: &str) -> Result<Self, regex::Error> {
+        let regex = regex::RegexBuilder::new(pattern)
+            .case_insensitive(flags.contains('i'))
+            .multi_line(flags.contains('m'))
+            .build()?;
+        Ok(Self {
+            regex,
+            flags: flags.to_string(),
+        })
+    }
+
+    /// The flags the regex was written with, e.g. `i` for `/ab+c/i`.
+    pub fn flags(&self) -> &str {
+        &self.flags
+    }
+}
+
+impl std::ops::Deref for RegexLiteral {
+    type Target = regex::Regex;
+
+    fn deref(&self) -> &Self::Target {
+        &self.regex
+    }
+}
+
+impl PartialEq for RegexLiteral {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str() && self.flags == other.flags
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum UnaryOp {
     Minus(Box<Ast>),
@@ -73,6 +115,7 @@ pub enum AstKind {
     Bool(bool),
     String(String),
     Number(f64),
+    Regex(RegexLiteral),
     Name(String),
     Var(String),
     Unary(UnaryOp),
@@ -95,6 +138,7 @@ pub enum AstKind {
         args: Vec<Ast>,
         body: Box<Ast>,
         thunk: bool,
+        signature: Option<Signature>,
     },
     Ternary {
         cond: Box<Ast>,
@@ -169,3 +213,209 @@ impl Ast {
         }
     }
 }
+
+/// Renders the AST as an indented tree, one node per line with its source position, e.g.
+///
+/// ```text
+/// Binary(+) @ 4
+///   Path @ 0
+///     Name(a) @ 0
+///     Name(b) @ 2
+///   Number(1) @ 6
+/// ```
+impl std::fmt::Display for Ast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_tree(f, 0)
+    }
+}
+
+impl Ast {
+    fn write_tree(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        fn label(f: &mut std::fmt::Formatter<'_>, depth: usize, text: &str) -> std::fmt::Result {
+            writeln!(f, "{:indent$}{}", "", text, indent = depth * 2)
+        }
+
+        fn pairs(
+            f: &mut std::fmt::Formatter<'_>,
+            depth: usize,
+            object: &Object,
+        ) -> std::fmt::Result {
+            for (key, value) in object {
+                key.write_tree(f, depth)?;
+                value.write_tree(f, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        fn terms(
+            f: &mut std::fmt::Formatter<'_>,
+            depth: usize,
+            terms: &SortTerms,
+        ) -> std::fmt::Result {
+            for (term, descending) in terms {
+                label(f, depth, if *descending { ">" } else { "<" })?;
+                term.write_tree(f, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        let name = match self.kind {
+            AstKind::Empty => "Empty".to_string(),
+            AstKind::Null => "Null".to_string(),
+            AstKind::Bool(b) => format!("Bool({})", b),
+            AstKind::String(ref s) => format!("String({:?})", s),
+            AstKind::Number(n) => format!("Number({})", n),
+            AstKind::Regex(ref r) => format!("Regex(/{}/{})", r.as_str(), r.flags()),
+            AstKind::Name(ref n) => format!("Name({})", n),
+            AstKind::Var(ref v) => format!("Var(${})", v),
+            AstKind::Unary(UnaryOp::Minus(..)) => "Minus".to_string(),
+            AstKind::Unary(UnaryOp::ArrayConstructor(..)) => "Array".to_string(),
+            AstKind::Unary(UnaryOp::ObjectConstructor(..)) => "Object".to_string(),
+            AstKind::Binary(ref op, ..) => format!("Binary({})", op),
+            AstKind::GroupBy(..) => "GroupBy".to_string(),
+            AstKind::OrderBy(..) => "OrderBy".to_string(),
+            AstKind::Block(..) => "Block".to_string(),
+            AstKind::Wildcard => "Wildcard".to_string(),
+            AstKind::Descendent => "Descendent".to_string(),
+            AstKind::Parent => "Parent".to_string(),
+            AstKind::Function {
+                ref name,
+                is_partial,
+                ..
+            } => format!(
+                "Function(${}){}",
+                name,
+                if is_partial { " partial" } else { "" }
+            ),
+            AstKind::PartialArg => "PartialArg".to_string(),
+            AstKind::Lambda {
+                thunk,
+                ref signature,
+                ..
+            } => format!(
+                "Lambda{}{}",
+                signature
+                    .as_ref()
+                    .map_or(String::new(), Signature::to_string),
+                if thunk { " thunk" } else { "" }
+            ),
+            AstKind::Ternary { .. } => "Ternary".to_string(),
+            AstKind::Transform { .. } => "Transform".to_string(),
+            AstKind::Path(..) => "Path".to_string(),
+            AstKind::Filter(..) => "Filter".to_string(),
+            AstKind::Sort(..) => "Sort".to_string(),
+            AstKind::Index(ref i) => format!("Index(${})", i),
+        };
+
+        let mut flags = String::new();
+        if self.keep_array {
+            flags.push_str(" keep_array");
+        }
+        if self.cons_array {
+            flags.push_str(" cons_array");
+        }
+        if self.keep_singleton_array {
+            flags.push_str(" keep_singleton_array");
+        }
+        if self.tuple {
+            flags.push_str(" tuple");
+        }
+        if let Some(ref focus) = self.focus {
+            flags.push_str(&format!(" @${}", focus));
+        }
+        if let Some(ref index) = self.index {
+            flags.push_str(&format!(" #${}", index));
+        }
+
+        label(
+            f,
+            depth,
+            &format!("{} @ {}{}", name, self.char_index, flags),
+        )?;
+
+        let depth = depth + 1;
+        match self.kind {
+            AstKind::Unary(UnaryOp::Minus(ref expr)) | AstKind::Filter(ref expr) => {
+                expr.write_tree(f, depth)?
+            }
+            AstKind::Unary(UnaryOp::ArrayConstructor(ref exprs))
+            | AstKind::Block(ref exprs)
+            | AstKind::Path(ref exprs) => {
+                for expr in exprs {
+                    expr.write_tree(f, depth)?;
+                }
+            }
+            AstKind::Unary(UnaryOp::ObjectConstructor(ref object)) => pairs(f, depth, object)?,
+            AstKind::Binary(_, ref lhs, ref rhs) => {
+                lhs.write_tree(f, depth)?;
+                rhs.write_tree(f, depth)?;
+            }
+            AstKind::GroupBy(ref lhs, ref object) => {
+                lhs.write_tree(f, depth)?;
+                pairs(f, depth, object)?;
+            }
+            AstKind::OrderBy(ref lhs, ref sort_terms) => {
+                lhs.write_tree(f, depth)?;
+                terms(f, depth, sort_terms)?;
+            }
+            AstKind::Sort(ref sort_terms) => terms(f, depth, sort_terms)?,
+            AstKind::Function { ref args, .. } => {
+                for arg in args {
+                    arg.write_tree(f, depth)?;
+                }
+            }
+            AstKind::Lambda {
+                ref args, ref body, ..
+            } => {
+                for arg in args {
+                    arg.write_tree(f, depth)?;
+                }
+                body.write_tree(f, depth)?;
+            }
+            AstKind::Ternary {
+                ref cond,
+                ref truthy,
+                ref falsy,
+            } => {
+                cond.write_tree(f, depth)?;
+                truthy.write_tree(f, depth)?;
+                if let Some(ref falsy) = falsy {
+                    falsy.write_tree(f, depth)?;
+                }
+            }
+            AstKind::Transform {
+                ref pattern,
+                ref update,
+                ref delete,
+            } => {
+                pattern.write_tree(f, depth)?;
+                update.write_tree(f, depth)?;
+                if let Some(ref delete) = delete {
+                    delete.write_tree(f, depth)?;
+                }
+            }
+            _ => (),
+        }
+
+        if let Some(ref predicates) = self.predicates {
+            label(f, depth, "predicates:")?;
+            for predicate in predicates {
+                predicate.write_tree(f, depth + 1)?;
+            }
+        }
+
+        if let Some(ref stages) = self.stages {
+            label(f, depth, "stages:")?;
+            for stage in stages {
+                stage.write_tree(f, depth + 1)?;
+            }
+        }
+
+        if let Some((_, ref object)) = self.group_by {
+            label(f, depth, "group_by:")?;
+            pairs(f, depth + 1, object)?;
+        }
+
+        Ok(())
+    }
+}
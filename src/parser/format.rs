@@ -0,0 +1,629 @@
+use super::ast::*;
+use super::tokenizer::{is_operator, is_whitespace};
+
+// The binding power of anything that can't be split by a surrounding operator, such as literals,
+// names, blocks and function calls
+const ATOM: u32 = 100;
+
+/// Renders an AST as canonical JSONata source, which parses back into an equivalent AST.
+///
+/// Binary operators are surrounded by single spaces, strings are double quoted and names are
+/// backtick quoted where needed. Parentheses are added where the structure of the AST differs
+/// from the precedence of its operators, so `(a + b) * c` built by hand is rendered with them.
+pub fn format_expression(ast: &Ast) -> String {
+    let mut out = String::new();
+    write_expression(&mut out, ast, 0);
+    out
+}
+
+// The binding powers follow the left binding powers of the parser's symbols
+fn binary_power(op: &BinaryOp) -> u32 {
+    match op {
+        BinaryOp::Range => 0,
+        BinaryOp::Bind => 10,
+        BinaryOp::Or => 25,
+        BinaryOp::And => 30,
+        BinaryOp::Equal
+        | BinaryOp::NotEqual
+        | BinaryOp::LessThan
+        | BinaryOp::GreaterThan
+        | BinaryOp::LessThanEqual
+        | BinaryOp::GreaterThanEqual
+        | BinaryOp::In
+        | BinaryOp::Apply => 40,
+        BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Concat => 50,
+        BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulus => 60,
+        BinaryOp::Map => 75,
+        BinaryOp::Predicate | BinaryOp::FocusBind | BinaryOp::IndexBind => 80,
+    }
+}
+
+/// The binding power of the loosest operator in the rendered node.
+fn precedence(ast: &Ast) -> u32 {
+    let power = kind_precedence(ast);
+
+    if ast.group_by.is_some() {
+        power.min(70)
+    } else {
+        power
+    }
+}
+
+/// The binding power of the node without any group-by that follows it.
+fn kind_precedence(ast: &Ast) -> u32 {
+    match ast.kind {
+        AstKind::Binary(ref op, ..) => binary_power(op),
+        AstKind::Ternary { .. } => 20,
+        AstKind::OrderBy(..) => 40,
+        AstKind::GroupBy(..) => 70,
+        AstKind::Unary(UnaryOp::Minus(..)) => 70,
+        AstKind::Number(n) if n.is_sign_negative() => 70,
+
+        // Sorting binds loosely, so a path that sorts is split by anything but a lower power
+        AstKind::Path(..) if is_sorted_path(ast) => 40,
+        AstKind::Path(ref steps) if steps.len() > 1 => 75,
+        AstKind::Path(ref steps) => steps.first().map_or(ATOM, precedence),
+        AstKind::Lambda {
+            thunk: true,
+            ref body,
+            ..
+        } => precedence(body),
+        _ => ATOM,
+    }
+}
+
+fn is_sorted_path(ast: &Ast) -> bool {
+    match ast.kind {
+        AstKind::Path(ref steps) => steps.iter().any(|s| matches!(s.kind, AstKind::Sort(..))),
+        _ => false,
+    }
+}
+
+/// Writes a node, in parentheses if it binds more loosely than `min_power` allows.
+fn write_expression(out: &mut String, ast: &Ast, min_power: u32) {
+    if precedence(ast) < min_power {
+        out.push('(');
+        write_node(out, ast);
+        out.push(')');
+    } else {
+        write_node(out, ast);
+    }
+}
+
+fn write_node(out: &mut String, ast: &Ast) {
+    write_kind(out, ast);
+
+    // A path is kept as a whole if its first name is, e.g. `a[].b`, but can also be kept after a
+    // sort, group-by or binding, e.g. `a^(b)[]` or `a{b: c}[]`
+    let keep_array = match ast.kind {
+        AstKind::Path(ref steps) => {
+            ast.keep_array
+                && !steps
+                    .first()
+                    .is_some_and(|step| step.keep_array && matches!(step.kind, AstKind::Name(..)))
+        }
+        _ => ast.keep_array,
+    };
+    let grouped = ast.group_by.is_some() || matches!(ast.kind, AstKind::Path(..));
+    if keep_array && !grouped {
+        out.push_str("[]");
+    }
+    if let Some(ref predicates) = ast.predicates {
+        for predicate in predicates {
+            write_kind(out, predicate);
+        }
+    }
+    if let Some(ref focus) = ast.focus {
+        out.push_str("@$");
+        out.push_str(focus);
+    }
+    if let Some(ref index) = ast.index {
+        out.push_str("#$");
+        out.push_str(index);
+    }
+    if let Some(ref stages) = ast.stages {
+        for stage in stages {
+            write_kind(out, stage);
+        }
+    }
+    if let Some((_, ref object)) = ast.group_by {
+        write_object(out, object);
+    }
+    if keep_array && grouped {
+        out.push_str("[]");
+    }
+}
+
+fn write_kind(out: &mut String, ast: &Ast) {
+    match ast.kind {
+        AstKind::Empty => (),
+        AstKind::Null => out.push_str("null"),
+        AstKind::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        AstKind::String(ref s) => write_string(out, s),
+        AstKind::Number(n) => out.push_str(&n.to_string()),
+        AstKind::Regex(ref r) => {
+            out.push('/');
+            out.push_str(r.as_str());
+            out.push('/');
+            out.push_str(r.flags());
+        }
+        AstKind::Name(ref name) => write_name(out, name),
+        AstKind::Var(ref name) => {
+            out.push('$');
+            out.push_str(name);
+        }
+        AstKind::Unary(UnaryOp::Minus(ref expr)) => {
+            out.push('-');
+            // A nested negation is itself a prefix, so it needs no parentheses
+            if matches!(expr.kind, AstKind::Unary(UnaryOp::Minus(..))) {
+                out.push(' ');
+                write_node(out, expr);
+            } else {
+                write_expression(out, expr, 71);
+            }
+        }
+        AstKind::Unary(UnaryOp::ArrayConstructor(ref exprs)) => {
+            out.push('[');
+            write_list(out, exprs, ", ");
+            out.push(']');
+        }
+        AstKind::Unary(UnaryOp::ObjectConstructor(ref object)) => write_object(out, object),
+        AstKind::Binary(ref op, ref lhs, ref rhs) => {
+            let power = binary_power(op);
+            match op {
+                BinaryOp::Range => {
+                    write_expression(out, lhs, 0);
+                    out.push_str("..");
+                    write_expression(out, rhs, 0);
+                }
+                BinaryOp::Map | BinaryOp::FocusBind | BinaryOp::IndexBind => {
+                    write_expression(out, lhs, power);
+                    out.push_str(&op.to_string());
+                    write_expression(out, rhs, power + 1);
+                }
+                BinaryOp::Predicate => {
+                    write_expression(out, lhs, power);
+                    out.push('[');
+                    write_expression(out, rhs, 0);
+                    out.push(']');
+                }
+                // Binding is right associative
+                BinaryOp::Bind => {
+                    write_expression(out, lhs, power + 1);
+                    out.push_str(" := ");
+                    write_expression(out, rhs, power);
+                }
+                _ => {
+                    // A sort ends at its closing parenthesis, so it can't capture the operator
+                    if is_sorted_path(lhs) {
+                        write_node(out, lhs);
+                    } else {
+                        write_expression(out, lhs, power);
+                    }
+                    out.push(' ');
+                    out.push_str(&op.to_string());
+                    out.push(' ');
+                    write_expression(out, rhs, power + 1);
+                }
+            }
+        }
+        AstKind::GroupBy(ref lhs, ref object) => {
+            write_expression(out, lhs, 70);
+            write_object(out, object);
+        }
+        AstKind::OrderBy(ref lhs, ref terms) => {
+            write_expression(out, lhs, 40);
+            write_sort_terms(out, terms);
+        }
+        AstKind::Block(ref exprs) => {
+            out.push('(');
+            write_list(out, exprs, "; ");
+            out.push(')');
+        }
+        AstKind::Wildcard => out.push('*'),
+        AstKind::Descendent => out.push_str("**"),
+        AstKind::Parent => out.push('%'),
+        AstKind::Function {
+            ref proc, ref args, ..
+        } => {
+            write_expression(out, proc, ATOM);
+            out.push('(');
+            write_list(out, args, ", ");
+            out.push(')');
+        }
+        AstKind::PartialArg => out.push('?'),
+
+        // Thunks are generated for calls in tail position, which are written as they were
+        AstKind::Lambda {
+            thunk: true,
+            ref body,
+            ..
+        } => write_node(out, body),
+        AstKind::Lambda {
+            ref args,
+            ref body,
+            ref signature,
+            ..
+        } => {
+            out.push_str("function(");
+            write_list(out, args, ", ");
+            out.push(')');
+            if let Some(ref signature) = signature {
+                out.push_str(&signature.to_string());
+            }
+            out.push_str(" { ");
+            write_expression(out, body, 0);
+            out.push_str(" }");
+        }
+        AstKind::Ternary {
+            ref cond,
+            ref truthy,
+            ref falsy,
+        } => {
+            write_expression(out, cond, 21);
+            out.push_str(" ? ");
+            write_expression(out, truthy, 0);
+            if let Some(ref falsy) = falsy {
+                out.push_str(" : ");
+                write_expression(out, falsy, 0);
+            }
+        }
+        AstKind::Transform {
+            ref pattern,
+            ref update,
+            ref delete,
+        } => {
+            out.push_str("| ");
+            write_expression(out, pattern, 0);
+            out.push_str(" | ");
+            write_expression(out, update, 0);
+            if let Some(ref delete) = delete {
+                out.push_str(", ");
+                write_expression(out, delete, 0);
+            }
+            out.push_str(" |");
+        }
+        AstKind::Path(ref steps) => {
+            for (index, step) in steps.iter().enumerate() {
+                // Sorting follows the step it sorts without a separator, e.g. `a.b^(c)`
+                if index > 0 && !matches!(step.kind, AstKind::Sort(..)) {
+                    out.push('.');
+                }
+
+                // A first step that's sorted straight away is just the left operand of `^`
+                let sorted = steps
+                    .get(index + 1)
+                    .is_some_and(|next| matches!(next.kind, AstKind::Sort(..)));
+                let min_power = if index == 0 && sorted { 40 } else { 75 };
+
+                // Nothing can follow a negation in a later step without being negated too, but a
+                // sort is applied to the whole path, e.g. `a.-b^(c)`
+                let last = sorted || index + 1 == steps.len();
+                let negation = matches!(step.kind, AstKind::Unary(UnaryOp::Minus(..)))
+                    || matches!(step.kind, AstKind::Number(n) if n.is_sign_negative());
+                let min_power = if index > 0 && last && negation && step.group_by.is_none() {
+                    70
+                } else {
+                    min_power
+                };
+
+                // The first step's group-by is applied before whatever follows, e.g. `(a){k: v}.b`
+                if index == 0 && step.group_by.is_some() && kind_precedence(step) >= min_power {
+                    write_node(out, step);
+                } else {
+                    write_expression(out, step, min_power);
+                }
+            }
+        }
+        AstKind::Filter(ref expr) => {
+            out.push('[');
+            write_expression(out, expr, 0);
+            out.push(']');
+        }
+        AstKind::Sort(ref terms) => write_sort_terms(out, terms),
+        AstKind::Index(ref index) => {
+            out.push_str("#$");
+            out.push_str(index);
+        }
+    }
+}
+
+fn write_list(out: &mut String, exprs: &[Ast], separator: &str) {
+    for (index, expr) in exprs.iter().enumerate() {
+        if index > 0 {
+            out.push_str(separator);
+        }
+        write_expression(out, expr, 0);
+    }
+}
+
+fn write_object(out: &mut String, object: &Object) {
+    out.push('{');
+    for (index, (key, value)) in object.iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        write_expression(out, key, 0);
+        out.push_str(": ");
+        write_expression(out, value, 0);
+    }
+    out.push('}');
+}
+
+fn write_sort_terms(out: &mut String, terms: &SortTerms) {
+    out.push_str("^(");
+    for (index, (term, descending)) in terms.iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        if *descending {
+            out.push('>');
+        }
+        write_expression(out, term, 0);
+    }
+    out.push(')');
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_name(out: &mut String, name: &str) {
+    let plain = name.chars().next().is_some_and(char::is_alphabetic)
+        && !name
+            .chars()
+            .any(|c| is_whitespace(c) || is_operator(c) || matches!(c, '`' | '\'' | '"'))
+        && !matches!(name, "and" | "or" | "in" | "true" | "false" | "null");
+
+    if plain {
+        out.push_str(name);
+    } else if name.contains('`') {
+        // Backticks can't be escaped, but a name with one can only come from a bare name such as
+        // a`b, or from a string step in a path, which is also a name
+        let bare = !name.starts_with(['`', '$', '\'', '"'])
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && !name.chars().any(|c| is_whitespace(c) || is_operator(c));
+        if bare {
+            out.push_str(name);
+        } else {
+            write_string(out, name);
+        }
+    } else {
+        out.push('`');
+        out.push_str(name);
+        out.push('`');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use proptest::prelude::*;
+    use test_case::test_case;
+
+    // The rendered tree without source positions, which differ between the original and the
+    // formatted source
+    fn tree(ast: &Ast) -> String {
+        let positions = regex::Regex::new(r" @ \d+").unwrap();
+        positions.replace_all(&ast.to_string(), "").to_string()
+    }
+
+    #[test_case("a+b*(c-d)", "a + b * (c - d)")]
+    #[test_case("(a+b)*c", "(a + b) * c")]
+    #[test_case("a-(b-c)", "a - (b - c)")]
+    #[test_case("-a.b", "-a.b")]
+    #[test_case("1 - -2", "1 - -2")]
+    #[test_case("Account.Order[0].Product", "Account.Order[0].Product")]
+    #[test_case("`field name`.`a.b`.and", "`field name`.`a.b`.`and`")]
+    #[test_case("'say \"hi\"'", "\"say \\\"hi\\\"\"")]
+    #[test_case("\"a\\\"b\\n\"", "\"a\\\"b\\n\"")]
+    #[test_case("x ? y: z", "x ? y : z")]
+    #[test_case("$f := function($a,$b){$a+$b}", "$f := function($a, $b) { $a + $b }")]
+    #[test_case("Product^(>Price,Quantity)", "Product^(>Price, Quantity)")]
+    #[test_case("Phone{type:number[]}", "Phone{type: number[]}")]
+    #[test_case("|a|{'b':1},['c']|", "| a | {\"b\": 1}, [\"c\"] |")]
+    #[test_case("$match(s, /a+b/i)", "$match(s, /a+b/i)")]
+    fn canonical(source: &str, expected: &str) {
+        assert_eq!(format_expression(&parse(source).unwrap()), expected);
+    }
+
+    #[test_case("Address1.City")]
+    #[test_case("Phone2[-1]")]
+    #[test_case("Phone4[[0..1]]")]
+    #[test_case("$[0].ref[0]")]
+    #[test_case("$.ref")]
+    #[test_case("Phone6[type='mobile'].number")]
+    #[test_case("*.Postcode1")]
+    #[test_case("**.Postcode2")]
+    #[test_case("FirstName & ' ' & Surname")]
+    #[test_case("Address3.(Street & ', ' & City)")]
+    #[test_case("(Numbers[2] != 0) and (Numbers[5] = Numbers[1]) or x in y")]
+    #[test_case("[Address4, Other.`Alternative.Address`].City")]
+    #[test_case("Email1.[address]")]
+    #[test_case("Phone8{type: number}")]
+    #[test_case("Phone9{type: number[]}")]
+    #[test_case("Account.Order.Product^(Price * Quantity)")]
+    #[test_case("student[type='fulltime']^(DoB).name")]
+    #[test_case("a[] ~> $sum()")]
+    #[test_case("$x[0][]")]
+    #[test_case("Account.Order@$o.Product#$i[$i > 1].{'o': $o.OrderID}")]
+    #[test_case("library.loans@$l.books[$l.isbn=isbn]")]
+    #[test_case("Invoice.($p := Product.Price; $q := Product.Quantity; $p * $q)")]
+    #[test_case("($f := function($x){ $x <= 1 ? 1 : $x * $f($x-1) }; $f(4))")]
+    #[test_case("($twice := function($f) { function($x){ $f($f($x)) } }; $twice($g)(7))")]
+    #[test_case("$substring(?, 0, 5)")]
+    #[test_case("$a ? $b ? 1 : 2 : $c ? 3")]
+    #[test_case("- -a")]
+    #[test_case("$x := $y := -5.25e3 / 2 % 3")]
+    #[test_case("$ ~> |Account.Order.Product|{'Price': Price * 1.2}, ['Tax']|")]
+    #[test_case("$replace('abc', /b(c)?/m, function($m) { $uppercase($m.match) })")]
+    #[test_case("$$.a")]
+    #[test_case("a^(>a)[]")]
+    #[test_case("-a^(>a)")]
+    #[test_case("a = a^(>a)")]
+    #[test_case("a^(>a){a: a} + a")]
+    #[test_case("(a){a: a}.a")]
+    #[test_case("a{a: a}[]")]
+    #[test_case("-a{a: a}[]")]
+    #[test_case("a.-a^(>a)")]
+    #[test_case("*[]@$v#$i[a][]")]
+    #[test_case("function($x, $y)<n-n?:n>{ $x + $y }")]
+    #[test_case("λ($a)< a<a<n>> (ns)+ >{ $a }")]
+    #[test_case("a`b")]
+    #[test_case("a.'`b c'")]
+    fn round_trip(source: &str) {
+        let ast = parse(source).unwrap();
+        let formatted = format_expression(&ast);
+        let reparsed = parse(&formatted).unwrap();
+
+        assert_eq!(tree(&ast), tree(&reparsed), "formatted as {}", formatted);
+        assert_eq!(format_expression(&reparsed), formatted);
+    }
+
+    #[test]
+    fn precedence_parentheses() {
+        let name = |n: &str| Ast::new(AstKind::Path(vec![Ast::new(AstKind::Name(n.into()), 0)]), 0);
+        let binary = |op, lhs, rhs| Ast::new(AstKind::Binary(op, Box::new(lhs), Box::new(rhs)), 0);
+
+        let sum = binary(BinaryOp::Add, name("a"), name("b"));
+        let ast = binary(BinaryOp::Multiply, sum.clone(), name("c"));
+        assert_eq!(format_expression(&ast), "(a + b) * c");
+
+        let ast = binary(BinaryOp::Subtract, name("c"), sum.clone());
+        assert_eq!(format_expression(&ast), "c - (a + b)");
+
+        let ast = binary(BinaryOp::Add, sum, name("c"));
+        assert_eq!(format_expression(&ast), "a + b + c");
+
+        let ternary = Ast::new(
+            AstKind::Ternary {
+                cond: Box::new(name("a")),
+                truthy: Box::new(name("b")),
+                falsy: None,
+            },
+            0,
+        );
+        let ast = Ast::new(AstKind::Unary(UnaryOp::Minus(Box::new(ternary))), 0);
+        assert_eq!(format_expression(&ast), "-(a ? b)");
+    }
+
+    // Generates mostly valid expressions from the building blocks of the grammar. Operands are
+    // parenthesised at random, so the formatter sees both redundant and necessary parentheses.
+    fn expression() -> impl Strategy<Value = String> {
+        let leaf = prop_oneof![
+            prop::sample::select(vec![
+                "a",
+                "b",
+                "Account",
+                "`field name`",
+                "`and`",
+                "*",
+                "**",
+                "$",
+                "$$",
+                "$x",
+                "$f",
+                "true",
+                "false",
+                "null",
+                "0",
+                "1.5",
+                "2e3",
+                "\"s\"",
+                "'it\\'s'",
+                "\"a\\nb\"",
+                "/ab+c/i",
+                "[]",
+                "{}",
+                "()",
+            ])
+            .prop_map(String::from),
+            (0u32..1000).prop_map(|n| n.to_string()),
+        ];
+
+        leaf.prop_recursive(4, 48, 4, |inner| {
+            let operand =
+                (inner.clone(), any::<bool>())
+                    .prop_map(|(e, wrap)| if wrap { format!("({e})") } else { e });
+            prop_oneof![
+                (
+                    operand.clone(),
+                    prop::sample::select(vec![
+                        "+", "-", "*", "/", "%", "&", "=", "!=", "<", "<=", ">", ">=", "and", "or",
+                        "in", "~>", ".",
+                    ]),
+                    operand.clone()
+                )
+                    .prop_map(|(l, op, r)| format!("{l} {op} {r}")),
+                operand.clone().prop_map(|e| format!("-{e}")),
+                (
+                    operand.clone(),
+                    operand.clone(),
+                    proptest::option::of(operand.clone())
+                )
+                    .prop_map(|(c, t, f)| match f {
+                        Some(f) => format!("{c} ? {t} : {f}"),
+                        None => format!("{c} ? {t}"),
+                    }),
+                (operand.clone(), operand.clone()).prop_map(|(e, p)| format!("{e}[{p}]")),
+                operand.clone().prop_map(|e| format!("{e}[]")),
+                (operand.clone(), operand.clone()).prop_map(|(e, k)| format!("{e}^(>{k})")),
+                (operand.clone(), operand.clone(), operand.clone())
+                    .prop_map(|(e, k, v)| format!("{e}{{{k}: {v}}}")),
+                (operand.clone(), operand.clone()).prop_map(|(k, v)| format!("{{{k}: {v}}}")),
+                prop::collection::vec(inner.clone(), 0..3)
+                    .prop_map(|items| format!("[{}]", items.join(", "))),
+                prop::collection::vec(inner.clone(), 1..3)
+                    .prop_map(|items| format!("({})", items.join("; "))),
+                prop::collection::vec(inner.clone(), 0..3)
+                    .prop_map(|args| format!("$f({})", args.join(", "))),
+                inner.clone().prop_map(|e| format!("$x := {e}")),
+                inner
+                    .clone()
+                    .prop_map(|e| format!("function($x, $y) {{ {e} }}")),
+                (operand.clone(), operand).prop_map(|(e, i)| format!("{e}@$v#$i[{i}]")),
+            ]
+        })
+    }
+
+    fn assert_round_trip(source: &str) -> Result<(), TestCaseError> {
+        let Ok(ast) = parse(source) else {
+            return Ok(());
+        };
+        let formatted = format_expression(&ast);
+        let reparsed = parse(&formatted)
+            .map_err(|e| TestCaseError::fail(format!("{formatted} doesn't parse: {e}")))?;
+
+        prop_assert_eq!(tree(&ast), tree(&reparsed), "formatted as {}", formatted);
+        prop_assert_eq!(format_expression(&reparsed), formatted);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn generated_round_trip(source in expression()) {
+            assert_round_trip(&source)?;
+        }
+
+        // Arbitrary text is mostly rejected, but must never panic the tokenizer or parser
+        #[test]
+        fn arbitrary_round_trip(source in "[ -~]{0,40}") {
+            assert_round_trip(&source)?;
+        }
+    }
+}
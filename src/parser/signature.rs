@@ -0,0 +1,177 @@
+use std::fmt;
+
+use super::tokenizer::is_whitespace;
+use crate::{Error, Result};
+
+/// The symbols of the types a parameter can have: boolean, number, string, null, object, array,
+/// function, any JSON value, and anything at all.
+const TYPES: &str = "bnsloafjx";
+
+/// The declared type of one parameter in a signature, e.g. `n`, `a<s>?` or `(ns)-`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    /// A type symbol, or each of the symbols in a choice group such as `(ns)`.
+    pub types: String,
+
+    /// The type of an array's members or the signature of a function, e.g. `s` in `a<s>`.
+    pub subtype: Option<String>,
+
+    /// Marked with `-`, so the context value is used when the argument is missing.
+    pub use_context: bool,
+
+    /// Marked with `?`.
+    pub optional: bool,
+
+    /// Marked with `+`, for one or more arguments of the type.
+    pub variadic: bool,
+}
+
+impl Param {
+    fn new(types: String) -> Self {
+        Self {
+            types,
+            subtype: None,
+            use_context: false,
+            optional: false,
+            variadic: false,
+        }
+    }
+}
+
+/// The signature of a lambda, e.g. `<n-n:n>` in `function($x, $y)<n-n:n>{ $x + $y }`, which
+/// declares the types of its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Param>,
+
+    /// The type after the `:`, which documents the result but is never checked.
+    pub result: Option<String>,
+}
+
+impl Signature {
+    /// Parses the text between the angle brackets of a signature, where `char_index` is the
+    /// position of its first character in the expression.
+    pub fn parse(source: &str, char_index: usize) -> Result<Self> {
+        let chars: Vec<(usize, char)> = source
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| !is_whitespace(*c))
+            .map(|(i, c)| (char_index + i, c))
+            .collect();
+        Self::parse_chars(&chars)
+    }
+
+    fn parse_chars(chars: &[(usize, char)]) -> Result<Self> {
+        let mut params: Vec<Param> = Vec::new();
+        let mut result = None;
+        let mut position = 0;
+
+        while let Some(&(char_index, c)) = chars.get(position) {
+            match c {
+                ':' => {
+                    let rest = &chars[position + 1..];
+                    if Self::parse_chars(rest)?.params.len() != 1 {
+                        return Err(Error::S0201SyntaxError(char_index, c.to_string()));
+                    }
+                    result = Some(rest.iter().map(|(_, c)| c).collect());
+                    break;
+                }
+                '(' => {
+                    let end = closing_bracket(chars, position, '(', ')')?;
+                    let choice: String = chars[position + 1..end].iter().map(|(_, c)| c).collect();
+                    if choice.contains('<') {
+                        return Err(Error::S0402ChoiceGroupTypeParameters(char_index));
+                    }
+                    if choice.is_empty() || !choice.chars().all(|c| TYPES.contains(c)) {
+                        return Err(Error::S0201SyntaxError(char_index, format!("({})", choice)));
+                    }
+                    params.push(Param::new(choice));
+                    position = end;
+                }
+                '<' => {
+                    let end = closing_bracket(chars, position, '<', '>')?;
+                    match params.last_mut() {
+                        Some(param)
+                            if (param.types == "a" || param.types == "f")
+                                && param.subtype.is_none() =>
+                        {
+                            let inner = &chars[position + 1..end];
+                            Self::parse_chars(inner)?;
+                            param.subtype = Some(inner.iter().map(|(_, c)| c).collect());
+                        }
+                        _ => return Err(Error::S0401TypeParametersNotAllowed(char_index)),
+                    }
+                    position = end;
+                }
+                '-' | '?' | '+' => match params.last_mut() {
+                    Some(param) if c == '-' => param.use_context = true,
+                    Some(param) if c == '?' => param.optional = true,
+                    Some(param) => param.variadic = true,
+                    None => return Err(Error::S0201SyntaxError(char_index, c.to_string())),
+                },
+                c if TYPES.contains(c) => params.push(Param::new(c.to_string())),
+                c => return Err(Error::S0201SyntaxError(char_index, c.to_string())),
+            }
+            position += 1;
+        }
+
+        Ok(Self { params, result })
+    }
+}
+
+/// The position of the bracket that closes the one at `start`.
+fn closing_bracket(
+    chars: &[(usize, char)],
+    start: usize,
+    open: char,
+    close: char,
+) -> Result<usize> {
+    let mut depth = 0;
+    for (position, &(_, c)) in chars.iter().enumerate().skip(start) {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Ok(position);
+            }
+        }
+    }
+    Err(Error::S0201SyntaxError(chars[start].0, open.to_string()))
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.types.len() > 1 {
+            write!(f, "({})", self.types)?;
+        } else {
+            write!(f, "{}", self.types)?;
+        }
+        if let Some(ref subtype) = self.subtype {
+            write!(f, "<{}>", subtype)?;
+        }
+        if self.use_context {
+            write!(f, "-")?;
+        }
+        if self.optional {
+            write!(f, "?")?;
+        }
+        if self.variadic {
+            write!(f, "+")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<")?;
+        for param in &self.params {
+            write!(f, "{}", param)?;
+        }
+        if let Some(ref result) = self.result {
+            write!(f, ":{}", result)?;
+        }
+        write!(f, ">")
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{Error, Result};
 
 use super::ast::*;
+use super::signature::Signature;
 use super::tokenizer::{Token, TokenKind};
 use super::Parser;
 
@@ -11,6 +12,8 @@ pub trait Symbol {
 }
 
 impl Symbol for Token {
+    // These follow the binding powers of the reference implementation. Note that `&` binds as
+    // tightly as `+` and `-`, and that `^` here is the order-by operator.
     fn left_binding_power(&self) -> u32 {
         use TokenKind::*;
         match &self.kind {
@@ -36,6 +39,7 @@ impl Symbol for Token {
             TokenKind::Bool(ref v) => Ok(Ast::new(AstKind::Bool(*v), self.char_index)),
             TokenKind::Str(ref v) => Ok(Ast::new(AstKind::String(v.clone()), self.char_index)),
             TokenKind::Number(v) => Ok(Ast::new(AstKind::Number(v), self.char_index)),
+            TokenKind::Regex(ref v) => Ok(Ast::new(AstKind::Regex(v.clone()), self.char_index)),
             TokenKind::Name(ref v) => Ok(Ast::new(AstKind::Name(v.clone()), self.char_index)),
             TokenKind::Var(ref v) => Ok(Ast::new(AstKind::Var(v.clone()), self.char_index)),
             TokenKind::And => Ok(Ast::new(
@@ -223,12 +227,21 @@ impl Symbol for Token {
                         name.clone()
                     }
                     AstKind::Var(ref name) => name.clone(),
-                    _ => unreachable!(),
+                    // Invoking the result of another expression, e.g. `$f(1)(2)`
+                    _ => String::new(),
                 };
 
                 let func: Ast;
 
                 if is_lambda {
+                    // The parameters can be followed by a signature, e.g. `function($x)<n:n>`
+                    let mut signature = None;
+                    if parser.token().kind == TokenKind::LeftAngleBracket {
+                        let (source, char_index) = parser.tokenizer.signature()?;
+                        signature = Some(Signature::parse(&source, char_index)?);
+                        parser.next_token()?;
+                    }
+
                     parser.expect(TokenKind::LeftBrace)?;
                     let body = Box::new(parser.expression(0)?);
                     func = Ast::new(
@@ -237,6 +250,7 @@ impl Symbol for Token {
                             args,
                             body,
                             thunk: false,
+                            signature,
                         },
                         self.char_index,
                     );
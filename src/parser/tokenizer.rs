@@ -2,6 +2,7 @@ use std::char::decode_utf16;
 use std::str::Chars;
 use std::{char, str};
 
+use super::ast::RegexLiteral;
 use crate::{Error, Result};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -60,6 +61,7 @@ pub enum TokenKind {
     Bool(bool),
     Str(String),
     Number(f64),
+    Regex(RegexLiteral),
 
     // Identifiers
     Name(String),
@@ -113,6 +115,7 @@ impl std::fmt::Display for TokenKind {
             Bool(v) => write!(f, "{}", v),
             Str(v) => write!(f, "\"{}\"", v),
             Number(v) => write!(f, "{}", v),
+            Regex(v) => write!(f, "/{}/", v.as_str()),
             Name(v) => write!(f, "{}", v),
             Var(v) => write!(f, "${}", v),
         }
@@ -147,12 +150,16 @@ pub struct Tokenizer<'a> {
 
     /// The starting char index of the current token being generated (used for errors)
     start_char_index: usize,
+
+    /// Whether the next token is in operand position, where a `/` starts a regex rather than
+    /// being the division operator
+    prefix: bool,
 }
 
 const NULL: char = '\0';
 
 #[inline]
-fn is_whitespace(c: char) -> bool {
+pub(super) fn is_whitespace(c: char) -> bool {
     matches!(
         c,
         '\u{0009}'   // \t
@@ -170,7 +177,7 @@ fn is_name_start(c: char) -> bool {
 }
 
 #[inline]
-fn is_operator(c: char) -> bool {
+pub(super) fn is_operator(c: char) -> bool {
     matches!(
         c,
         '.' | '['
@@ -211,6 +218,7 @@ impl<'a> Tokenizer<'a> {
             char_index: 0,
             start_byte_index: 0,
             start_char_index: 0,
+            prefix: true,
         }
     }
 
@@ -249,6 +257,32 @@ impl<'a> Tokenizer<'a> {
         String::from(&self.input[token.byte_index..token.byte_index + token.len])
     }
 
+    /// Reads the raw text of a function signature, up to the `>` that closes the `<` token just
+    /// read, returning it with the char index it starts at.
+    pub fn signature(&mut self) -> Result<(String, usize)> {
+        let start_byte_index = self.byte_index;
+        let start_char_index = self.char_index;
+        let mut depth = 1;
+
+        loop {
+            if self.eof() {
+                return Err(Error::S0203ExpectedTokenBeforeEnd(
+                    self.char_index,
+                    ">".to_string(),
+                ));
+            }
+            match self.bump() {
+                '<' => depth += 1,
+                '>' if depth == 1 => break,
+                '>' => depth -= 1,
+                _ => (),
+            }
+        }
+
+        let source = &self.input[start_byte_index..self.byte_index - 1];
+        Ok((source.to_string(), start_char_index))
+    }
+
     fn get_hex_digit(&mut self) -> Result<u16> {
         let ch = self.bump();
         if ch.len_utf8() != 1 {
@@ -287,32 +321,32 @@ impl<'a> Tokenizer<'a> {
                 }
 
                 // Comments, forward-slashes or regexp
-                // TODO: Regexp
                 '/' => match self.peek() {
                     '*' => {
                         // Skip the *
                         self.bump();
 
+                        // Comments don't nest, so the first */ ends the comment
                         loop {
                             // Eat until the next *
                             self.eat_while(|c| c != '*');
 
-                            // Skip the *
-                            self.bump();
-
                             // Check for unterminated comments
                             if self.eof() {
                                 return Err(Error::S0106UnterminatedComment(self.start_char_index));
                             }
 
-                            // Is this the end of the comment?
-                            if self.bump() == '/' {
+                            // Skip the *, and the / if this is the end of the comment
+                            self.bump();
+                            if self.peek() == '/' {
+                                self.bump();
                                 break;
                             }
                         }
 
                         Comment
                     }
+                    _ if self.prefix => self.scan_regex()?,
                     _ => ForwardSlash,
                 },
 
@@ -419,6 +453,7 @@ impl<'a> Tokenizer<'a> {
                             '\\' => match self.bump() {
                                 '\\' => self.buffer.push('\\'),
                                 '"' => self.buffer.push('"'),
+                                '/' => self.buffer.push('/'),
                                 'b' => self.buffer.push('\x08'),
                                 'f' => self.buffer.push('\x0c'),
                                 'n' => self.buffer.push('\n'),
@@ -546,6 +581,26 @@ impl<'a> Tokenizer<'a> {
             }
         };
 
+        // Anything that can end an operand is followed by an operator. That includes `and`, `or`
+        // and `in` when they appear in operand position, e.g. `data.and`, where they're field names.
+        let operand = matches!(
+            kind,
+            Name(..)
+                | Var(..)
+                | Str(..)
+                | Number(..)
+                | Bool(..)
+                | Null
+                | Regex(..)
+                | RightParen
+                | RightBracket
+                | RightBrace
+                | Asterisk
+                | Descendent
+                | PercentSign
+        ) || (self.prefix && matches!(kind, And | Or | In));
+        self.prefix = !operand;
+
         let token = Token {
             kind,
             char_index: self.start_char_index,
@@ -556,6 +611,40 @@ impl<'a> Tokenizer<'a> {
         Ok(token)
     }
 
+    fn scan_regex(&mut self) -> Result<TokenKind> {
+        let pattern_start = self.byte_index;
+        let mut depth = 0;
+        let mut escaped = false;
+
+        // A `/` only ends the regex outside of any brackets, and when it isn't escaped
+        loop {
+            if self.eof() {
+                return Err(Error::S0302UnterminatedRegex(self.start_char_index));
+            }
+            match self.bump() {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '/' if depth == 0 => break,
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                _ => (),
+            }
+        }
+
+        let pattern = &self.input[pattern_start..self.byte_index - 1];
+        if pattern.is_empty() {
+            return Err(Error::S0301EmptyRegex(self.start_char_index));
+        }
+
+        let flags_start = self.byte_index;
+        self.eat_while(|c| c == 'i' || c == 'm');
+        let flags = &self.input[flags_start..self.byte_index];
+
+        RegexLiteral::new(pattern, flags)
+            .map(TokenKind::Regex)
+            .map_err(|e| Error::S0303InvalidRegex(self.start_char_index, e.to_string()))
+    }
+
     fn scan_number(&mut self) -> Result<TokenKind> {
         loop {
             match self.peek() {
@@ -610,6 +699,47 @@ mod tests {
         assert!(matches!(t.next_token().unwrap().kind, TokenKind::End));
     }
 
+    #[test]
+    fn comment_at_start() {
+        let mut t = Tokenizer::new("/* leading **/ a");
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Name(s) if s == "a"));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::End));
+    }
+
+    #[test]
+    fn comment_between_tokens() {
+        let mut t = Tokenizer::new("a /* one /* two */ / b");
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Name(s) if s == "a"));
+        assert!(matches!(
+            t.next_token().unwrap().kind,
+            TokenKind::ForwardSlash
+        ));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Name(s) if s == "b"));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::End));
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        let mut t = Tokenizer::new("a /* never closed");
+        t.next_token().unwrap();
+        assert!(matches!(
+            t.next_token(),
+            Err(Error::S0106UnterminatedComment(2))
+        ));
+
+        let mut t = Tokenizer::new("/* almost *");
+        assert!(matches!(
+            t.next_token(),
+            Err(Error::S0106UnterminatedComment(0))
+        ));
+
+        let mut t = Tokenizer::new("/*/");
+        assert!(matches!(
+            t.next_token(),
+            Err(Error::S0106UnterminatedComment(0))
+        ));
+    }
+
     #[test]
     fn operators() {
         let mut t = Tokenizer::new("@..[]{}()=^&,~>#+<=:=>=!=?-***");
@@ -696,6 +826,32 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn backtick_names_with_special_characters() {
+        let mut t = Tokenizer::new("`field name`.`a.b`");
+        assert!(matches!(
+            t.next_token().unwrap().kind,
+            TokenKind::Name(s) if s == "field name"
+        ));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Period));
+        assert!(matches!(
+            t.next_token().unwrap().kind,
+            TokenKind::Name(s) if s == "a.b"
+        ));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::End));
+    }
+
+    #[test]
+    fn unterminated_backtick_name() {
+        let mut t = Tokenizer::new("foo.`bar");
+        t.next_token().unwrap();
+        t.next_token().unwrap();
+        assert!(matches!(
+            t.next_token(),
+            Err(Error::S0105UnterminatedQuoteProp(4))
+        ));
+    }
+
     #[test]
     fn variables() {
         let mut t = Tokenizer::new("  $one   $two   $three  ");
@@ -713,6 +869,36 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn context_variables() {
+        let mut t = Tokenizer::new("$ $$.config");
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Var(s) if s.is_empty()));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Var(s) if s == "$"));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Period));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Name(s) if s == "config"));
+    }
+
+    #[test]
+    fn tilde() {
+        let mut t = Tokenizer::new("~> ~ > ~");
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Apply));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Tilde));
+        assert!(matches!(
+            t.next_token().unwrap().kind,
+            TokenKind::RightAngleBracket
+        ));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::Tilde));
+        assert!(matches!(t.next_token().unwrap().kind, TokenKind::End));
+    }
+
+    #[test]
+    fn escaped_solidus() {
+        let mut t = Tokenizer::new(r#""http:\/\/example.com/a""#);
+        assert!(
+            matches!(t.next_token().unwrap().kind, TokenKind::Str(s) if s == "http://example.com/a")
+        );
+    }
+
     #[test]
     fn name_operators() {
         let mut t = Tokenizer::new("or in and");